@@ -0,0 +1,104 @@
+// Chariot: An open source reimplementation of Age of Empires (1997)
+// Copyright (c) 2016 Kevin Fuller
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+//
+
+use crate::drs::{DrsFileType, DrsHeader};
+use crate::error::*;
+use crate::reader::{build_index_map, read_raw_headers, DrsRawTables};
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::SeekFrom;
+use std::path::Path;
+
+/// An eagerly-loaded DRS archive that keeps every file's contents in one contiguous
+/// buffer instead of a `Vec<u8>` per file. Loading takes a single allocation, and files
+/// that are processed in archive order are read from memory sequentially.
+pub struct DrsArenaFile {
+    pub header: DrsHeader,
+    tables: DrsRawTables,
+    index_map: HashMap<(DrsFileType, u32), (usize, usize)>,
+    /// The (start, length) in `arena` of each entry's contents, parallel to `tables`.
+    spans: Vec<Vec<(usize, usize)>>,
+    arena: Vec<u8>,
+}
+
+impl DrsArenaFile {
+    /// Loads a DRS archive from the file system into a single buffer.
+    pub fn read_from_file<P: AsRef<Path>>(file_name: P) -> Result<DrsArenaFile> {
+        let file_name = file_name.as_ref();
+        let mut file = File::open(file_name)?;
+        let (header, tables) = read_raw_headers(&mut file, file_name)?;
+        let file_len = file.seek(SeekFrom::End(0))?;
+
+        let mut total = 0u64;
+        for (_, entries) in &tables {
+            for entry in entries {
+                entry.check_fits(file_len)?;
+                total += entry.file_size;
+            }
+        }
+
+        let mut arena = vec![0u8; total as usize];
+        let mut spans = Vec::with_capacity(tables.len());
+        let mut start = 0usize;
+        for (_, entries) in &tables {
+            let mut table_spans = Vec::with_capacity(entries.len());
+            for entry in entries {
+                let len = entry.file_size as usize;
+                file.seek(SeekFrom::Start(entry.file_offset))?;
+                file.read_exact(&mut arena[start..start + len])?;
+                table_spans.push((start, len));
+                start += len;
+            }
+            spans.push(table_spans);
+        }
+
+        let index_map = build_index_map(&tables);
+        Ok(DrsArenaFile {
+            header,
+            tables,
+            index_map,
+            spans,
+            arena,
+        })
+    }
+
+    /// The table headers of the archive, each paired with the entries of that table.
+    pub fn tables(&self) -> &DrsRawTables {
+        &self.tables
+    }
+
+    /// The contents of every file, back-to-back in archive order.
+    pub fn arena(&self) -> &[u8] {
+        &self.arena
+    }
+
+    /// Returns a file's contents as a slice of the shared buffer, or None if the archive
+    /// doesn't contain it.
+    pub fn find_file_contents(&self, file_type: DrsFileType, file_id: u32) -> Option<&[u8]> {
+        self.index_map.get(&(file_type, file_id)).map(|&(table_index, entry_index)| {
+            let (start, len) = self.spans[table_index][entry_index];
+            &self.arena[start..start + len]
+        })
+    }
+}
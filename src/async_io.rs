@@ -0,0 +1,234 @@
+// Chariot: An open source reimplementation of Age of Empires (1997)
+// Copyright (c) 2016 Kevin Fuller
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+//
+
+use crate::drs::{data_end, DrsFile, DrsHeader, DrsReadOptions, MAX_HEADER_LEN, TABLE_HEADER_LEN};
+use crate::error::*;
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeek, AsyncSeekExt};
+
+use std::cmp;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+
+impl DrsFile {
+    /// Loads a DRS archive from an async reader, such as a `tokio::fs::File`, without
+    /// blocking the executor. The archive must start at offset 0 of the reader. The header,
+    /// tables, and entries are fetched with awaited reads and then parsed exactly as
+    /// `read_from` parses them, followed by the contents of each file in turn. Errors that
+    /// name a path use `<stream>`.
+    pub async fn read_from_async<R: AsyncRead + AsyncSeek + Unpin>(reader: &mut R) -> Result<DrsFile> {
+        let file_name = Path::new("<stream>");
+        let file_len = reader.seek(SeekFrom::End(0)).await?;
+
+        // Each part of the metadata says how long the next is: the header gives the number
+        // of table headers, and each table header ends with the number of entries it has.
+        let mut metadata = Vec::new();
+        fetch_to(reader, &mut metadata, cmp::min(MAX_HEADER_LEN, file_len)).await?;
+        let header = DrsHeader::read_from(&mut Fetched::new(&metadata, 0, file_len), file_name)?;
+        let tables_start = header.serialized_len();
+        let tables_end = tables_start + TABLE_HEADER_LEN * header.table_count as u64;
+        // Bogus counts are rejected before fetching anything they describe, with the same
+        // errors `read_table_headers` and `read_file_entry_headers` give.
+        if tables_end > file_len {
+            let reason = format!("{} table headers don't fit in a {} byte archive", header.table_count, file_len);
+            return Err(ErrorKind::ImplausibleHeader(reason).into());
+        }
+        fetch_to(reader, &mut metadata, tables_end).await?;
+        let entry_len = DrsReadOptions::default().offset_width.entry_len();
+        let mut entries_end = tables_end;
+        let table_headers = metadata[tables_start as usize..tables_end as usize].chunks_exact(TABLE_HEADER_LEN as usize);
+        for (table_index, table_header) in table_headers.enumerate() {
+            let table_offset = u32::from_le_bytes([table_header[4], table_header[5], table_header[6], table_header[7]]);
+            let file_count = u32::from_le_bytes([table_header[8], table_header[9], table_header[10], table_header[11]]);
+            let table_end = table_offset as u64 + entry_len * file_count as u64;
+            entries_end += entry_len * file_count as u64;
+            if table_end > file_len || entries_end > file_len {
+                let reason = format!("the {} entries of table {} don't fit in a {} byte archive",
+                                     file_count,
+                                     table_index,
+                                     file_len);
+                return Err(ErrorKind::ImplausibleHeader(reason).into());
+            }
+        }
+        fetch_to(reader, &mut metadata, entries_end).await?;
+
+        let mut drs_file = DrsFile::read_metadata_from(&mut Fetched::new(&metadata, 0, file_len),
+                                                       file_name,
+                                                       DrsReadOptions::default())?;
+        drs_file.check_contents_fit(file_len)?;
+        for table in &mut drs_file.tables {
+            table.contents.reserve(table.entries.len());
+            for entry in &table.entries {
+                let mut contents = vec![0u8; entry.file_size as usize];
+                read_exact_at(reader, &mut contents, entry.file_offset, "file contents").await?;
+                table.contents.push(contents);
+            }
+        }
+
+        let trailer_start = cmp::min(data_end(&drs_file), file_len);
+        let mut trailer = vec![0u8; (file_len - trailer_start) as usize];
+        read_exact_at(reader, &mut trailer, trailer_start, "build tag").await?;
+        drs_file.finish_contents(&mut Fetched::new(&trailer, trailer_start, file_len))?;
+        Ok(drs_file)
+    }
+}
+
+/// Extends the fetched metadata so that it covers the first `len` bytes of the stream.
+async fn fetch_to<R: AsyncRead + AsyncSeek + Unpin>(reader: &mut R, metadata: &mut Vec<u8>, len: u64) -> Result<()> {
+    let start = metadata.len();
+    if len > start as u64 {
+        metadata.resize(len as usize, 0);
+        read_exact_at(reader, &mut metadata[start..], start as u64, "archive metadata").await?;
+    }
+    Ok(())
+}
+
+/// Fills the buffer from the given offset, failing with `ReadFailed` like the sync reads do.
+async fn read_exact_at<R: AsyncRead + AsyncSeek + Unpin>(reader: &mut R,
+                                                          buf: &mut [u8],
+                                                          at: u64,
+                                                          what: &'static str)
+                                                          -> Result<()> {
+    reader.seek(SeekFrom::Start(at)).await?;
+    reader.read_exact(buf).await.chain_err(|| ErrorKind::ReadFailed { at, what })?;
+    Ok(())
+}
+
+/// A stretch of the stream that has already been fetched, presented to the sync parser as
+/// the whole stream: seeks are relative to the full length, and reads outside the stretch
+/// find nothing.
+struct Fetched<'a> {
+    bytes: &'a [u8],
+    start: u64,
+    len: u64,
+    position: u64,
+}
+
+impl<'a> Fetched<'a> {
+    fn new(bytes: &'a [u8], start: u64, len: u64) -> Fetched<'a> {
+        Fetched {
+            bytes,
+            start,
+            len,
+            position: 0,
+        }
+    }
+}
+
+impl<'a> Read for Fetched<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = match self.position.checked_sub(self.start) {
+            Some(offset) if offset < self.bytes.len() as u64 => &self.bytes[offset as usize..],
+            _ => return Ok(0),
+        };
+        let read = cmp::min(buf.len(), available.len());
+        buf[..read].copy_from_slice(&available[..read]);
+        self.position += read as u64;
+        Ok(read)
+    }
+}
+
+impl<'a> Seek for Fetched<'a> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(offset) => offset as i128,
+            SeekFrom::End(offset) => self.len as i128 + offset as i128,
+            SeekFrom::Current(offset) => self.position as i128 + offset as i128,
+        };
+        self.position = target.clamp(0, self.len as i128) as u64;
+        Ok(self.position)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::DrsBuilder;
+    use crate::drs::{DrsFileType, DrsGameType};
+    use crate::writer::DrsWriteOptions;
+    use crate::fixtures::{make_test_archive, make_truncated_archive, write_temp_archive};
+
+    /// Reads the archive from a file both ways, with the sync error messages naming the
+    /// path the way the async ones do.
+    async fn read_both(name: &str, bytes: &[u8]) -> (Result<DrsFile>, Result<DrsFile>) {
+        let path = write_temp_archive(name, bytes);
+        let mut file = tokio::fs::File::open(&path).await.unwrap();
+        let async_read = DrsFile::read_from_async(&mut file).await;
+        let sync_read = DrsFile::read_from_file(&path).map_err(|err| {
+            let message = err.to_string().replace(&format!("{:?}", &*path), "\"<stream>\"");
+            Error::from(message)
+        });
+        (async_read, sync_read)
+    }
+
+    #[tokio::test]
+    async fn async_read_matches_sync_read() {
+        let bytes = make_test_archive(DrsGameType::SWBG,
+                                      &[(DrsFileType::Binary, 50500, b"palette"),
+                                        (DrsFileType::Slp, 1, b"SLP"),
+                                        (DrsFileType::Wav, 7, b"RIFF")]);
+        let (async_read, sync_read) = read_both("async.drs", &bytes).await;
+        let (async_read, sync_read) = (async_read.unwrap(), sync_read.unwrap());
+        assert_eq!(async_read, sync_read);
+        assert_eq!(async_read.find_file(7).unwrap(), b"RIFF");
+    }
+
+    #[tokio::test]
+    async fn async_read_of_aoe_archive_without_tables() {
+        // The SWBG-sized header fetch runs past the (empty) table headers into the build tag.
+        let options = DrsWriteOptions {
+            build_tag: Some("a build tag longer than twenty bytes".into()),
+            ..DrsWriteOptions::default()
+        };
+        let mut bytes = Vec::new();
+        DrsBuilder::new(DrsGameType::AOE).build().unwrap().write_to_with_options(&mut bytes, &options).unwrap();
+        let (async_read, sync_read) = read_both("async_no_tables.drs", &bytes).await;
+        let (async_read, sync_read) = (async_read.unwrap(), sync_read.unwrap());
+        assert_eq!(async_read, sync_read);
+        assert_eq!(async_read.build_tag(), Some("a build tag longer than twenty bytes".to_string()));
+        assert_eq!(sync_read.build_tag(), async_read.build_tag());
+    }
+
+    #[tokio::test]
+    async fn async_read_fails_like_sync_read() {
+        let bytes = make_truncated_archive(DrsGameType::AOE);
+        for &len in &[0, 10, 70, 80, 100, bytes.len()] {
+            let (async_read, sync_read) = read_both("async_truncated.drs", &bytes[..len]).await;
+            assert_eq!(async_read.unwrap_err().to_string(), sync_read.unwrap_err().to_string());
+        }
+    }
+
+    #[tokio::test]
+    async fn async_read_rejects_implausible_counts_like_sync_read() {
+        let bytes = make_test_archive(DrsGameType::AOE, &[(DrsFileType::Slp, 1, b"SLP")]);
+        let header_len = DrsHeader::new(DrsGameType::AOE).serialized_len() as usize;
+        // The table count ends the header; the entry count ends the first table header.
+        for &count_at in &[header_len - 8, header_len + 8] {
+            let mut bogus = bytes.clone();
+            bogus[count_at..count_at + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+            let (async_read, sync_read) = read_both("async_implausible.drs", &bogus).await;
+            let (async_err, sync_err) = (async_read.unwrap_err(), sync_read.unwrap_err());
+            assert!(matches!(async_err, Error(ErrorKind::ImplausibleHeader(_), _)));
+            assert_eq!(async_err.to_string(), sync_err.to_string());
+        }
+    }
+}
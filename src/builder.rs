@@ -0,0 +1,118 @@
+// Chariot: An open source reimplementation of Age of Empires (1997)
+// Copyright (c) 2016 Kevin Fuller
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+//
+
+use crate::drs::{DrsFile, DrsFileContents, DrsFileType, DrsGameType, DrsHeader, DrsLogicalTable};
+use crate::error::*;
+
+/// Assembles a `DrsFile` from scratch. Files are grouped into one table per file type,
+/// with tables appearing in the order their types were first added. File IDs are always
+/// kept exactly as given, so references between files (such as an SLP naming the ID of
+/// its palette in the Binary table) stay intact.
+pub struct DrsBuilder {
+    game_type: DrsGameType,
+    tables: Vec<DrsLogicalTable>,
+    /// (referencing file, ID of the Binary file it references)
+    references: Vec<((DrsFileType, u32), u32)>,
+}
+
+impl DrsBuilder {
+    pub fn new(game_type: DrsGameType) -> DrsBuilder {
+        DrsBuilder {
+            game_type,
+            tables: Vec::new(),
+            references: Vec::new(),
+        }
+    }
+
+    /// Makes sure the archive has a table of the given type, even if no files of that
+    /// type are ever added. An empty table is written out with a file count of zero.
+    pub fn add_table(&mut self, file_type: DrsFileType) {
+        self.table_mut(file_type);
+    }
+
+    /// Adds a file to the table for its type. Fails with `DuplicateFileId` if that table
+    /// already has a file with the same ID.
+    pub fn add_file(&mut self, file_type: DrsFileType, file_id: u32, contents: DrsFileContents) -> Result<()> {
+        self.table_mut(file_type).push_file(file_id, contents)
+    }
+
+    /// Records that `from` refers to the file with ID `to_binary_id` in the Binary table,
+    /// typically an SLP and its palette. `build` fails if the referenced file was never added.
+    pub fn add_reference_check(&mut self, from: (DrsFileType, u32), to_binary_id: u32) {
+        self.references.push((from, to_binary_id));
+    }
+
+    /// Produces the archive. Offsets are left at zero; they're computed when it's written.
+    /// Fails with `BrokenReference` if a file registered with `add_reference_check`
+    /// refers to a Binary file that isn't in the archive.
+    pub fn build(self) -> Result<DrsFile> {
+        for &((file_type, file_id), to_binary_id) in &self.references {
+            let found = self.tables
+                .iter()
+                .filter(|t| t.header.file_type == DrsFileType::Binary)
+                .any(|t| t.entries.iter().any(|e| e.file_id == to_binary_id));
+            if !found {
+                return Err(ErrorKind::BrokenReference(file_type, file_id, to_binary_id).into());
+            }
+        }
+
+        let mut drs_file = DrsFile::empty();
+        drs_file.header = DrsHeader::new(self.game_type);
+        drs_file.header.table_count = self.tables.len() as u32;
+        drs_file.tables = self.tables;
+        Ok(drs_file)
+    }
+
+    fn table_mut(&mut self, file_type: DrsFileType) -> &mut DrsLogicalTable {
+        match self.tables.iter().position(|t| t.header.file_type == file_type) {
+            Some(index) => &mut self.tables[index],
+            None => {
+                let mut table = DrsLogicalTable::new();
+                table.header.file_type = file_type;
+                self.tables.push(table);
+                self.tables.last_mut().unwrap()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::writer::DrsWriteOptions;
+
+    #[test]
+    fn empty_table_survives_a_round_trip() {
+        let mut builder = DrsBuilder::new(DrsGameType::AOE);
+        builder.add_table(DrsFileType::Slp);
+        builder.add_file(DrsFileType::Wav, 7, b"RIFF".to_vec()).unwrap();
+        let mut bytes = Vec::new();
+        builder.build().unwrap().write_to_with_options(&mut bytes, &DrsWriteOptions::default()).unwrap();
+
+        let drs_file = DrsFile::from_bytes(&bytes).unwrap();
+        assert_eq!(drs_file.header.table_count, 2);
+        let slp = drs_file.find_table(DrsFileType::Slp).unwrap();
+        assert_eq!(slp.header.file_count, 0);
+        assert!(slp.entries.is_empty());
+        assert_eq!(drs_file.find_file(7).unwrap(), b"RIFF");
+    }
+}
@@ -0,0 +1,173 @@
+// Chariot: An open source reimplementation of Age of Empires (1997)
+// Copyright (c) 2016 Kevin Fuller
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+//
+
+use crate::drs::{known_file_type, DrsFile, DrsFileType, DrsGameType, DrsHeader, DrsTableEntry, DrsTableHeader};
+use crate::error::*;
+use crate::reader::{DrsRawTables, DrsReader};
+
+use chariot_io_tools::byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+use std::io::prelude::*;
+use std::path::Path;
+
+/// Identifies an entry cache written by `DrsFile::entries_to_cache`.
+const CACHE_MAGIC: &[u8; 8] = b"DRSCACHE";
+/// Bumped whenever the cache layout changes, so stale caches are rejected, not misread.
+const CACHE_VERSION: u32 = 1;
+
+impl DrsFile {
+    /// Writes the header and every table and entry, without any file contents, in a stable
+    /// little-endian format that `entries_from_cache` and `DrsReader::open_with_cache` read
+    /// back. The cache doesn't record which archive it came from; it's up to the caller to
+    /// only use it with an unchanged copy of that archive (e.g. by keying it on mtime).
+    pub fn entries_to_cache<W: Write>(&self, mut out: W) -> Result<()> {
+        out.write_all(CACHE_MAGIC)?;
+        out.write_u32::<LittleEndian>(CACHE_VERSION)?;
+        out.write_u32::<LittleEndian>(self.header.serialized_len() as u32)?;
+        self.header.write_to(&mut out)?;
+        out.write_u32::<LittleEndian>(self.tables.len() as u32)?;
+        for table in &self.tables {
+            out.write_u32::<LittleEndian>(table.header.file_type.to_u32())?;
+            out.write_u32::<LittleEndian>(table.header.table_offset)?;
+            out.write_u32::<LittleEndian>(table.entries.len() as u32)?;
+            for entry in &table.entries {
+                out.write_u32::<LittleEndian>(entry.file_id)?;
+                out.write_u64::<LittleEndian>(entry.file_offset)?;
+                out.write_u64::<LittleEndian>(entry.file_size)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Opens an archive using metadata from a cache written by `entries_to_cache` instead of
+    /// parsing it from the archive. Nothing is read from `archive_path` up front; file
+    /// contents are read on demand, at the offsets recorded in the cache, through the
+    /// returned reader. This is `DrsReader::open_with_cache`.
+    pub fn entries_from_cache<P: AsRef<Path>, R: Read>(archive_path: P, cache: R) -> Result<DrsReader> {
+        DrsReader::open_with_cache(archive_path, cache)
+    }
+}
+
+/// Reads a cache written by `DrsFile::entries_to_cache`.
+pub(crate) fn read_cache<R: Read>(mut cache: R) -> Result<(DrsHeader, DrsRawTables)> {
+    let mut magic = [0u8; 8];
+    cache.read_exact(&mut magic)?;
+    if &magic != CACHE_MAGIC {
+        return Err(ErrorKind::InvalidCache("not an entry cache".into()).into());
+    }
+    let version = cache.read_u32::<LittleEndian>()?;
+    if version != CACHE_VERSION {
+        let reason = format!("unsupported cache version {}", version);
+        return Err(ErrorKind::InvalidCache(reason).into());
+    }
+
+    let header = read_cached_header(&mut cache)?;
+    let table_count = cache.read_u32::<LittleEndian>()?;
+    if table_count != header.table_count {
+        let reason = format!("the header counts {} tables but the cache has {}", header.table_count, table_count);
+        return Err(ErrorKind::InvalidCache(reason).into());
+    }
+    let mut tables = Vec::new();
+    for _table_index in 0..table_count {
+        let tag = cache.read_u32::<LittleEndian>()?;
+        let table_header = DrsTableHeader {
+            file_type: known_file_type(tag).unwrap_or(DrsFileType::Unknown(tag)),
+            table_offset: cache.read_u32::<LittleEndian>()?,
+            file_count: cache.read_u32::<LittleEndian>()?,
+        };
+        let mut entries = Vec::new();
+        for _file_index in 0..table_header.file_count {
+            entries.push(DrsTableEntry {
+                file_id: cache.read_u32::<LittleEndian>()?,
+                file_offset: cache.read_u64::<LittleEndian>()?,
+                file_size: cache.read_u64::<LittleEndian>()?,
+            });
+        }
+        tables.push((table_header, entries));
+    }
+    Ok((header, tables))
+}
+
+/// Reads a header written by `DrsHeader::write_to`, preceded by its length. The length
+/// identifies the game, since only the copyright's length differs between them.
+fn read_cached_header<R: Read>(cache: &mut R) -> Result<DrsHeader> {
+    let header_len = cache.read_u32::<LittleEndian>()? as u64;
+    let game_type = [DrsGameType::AOE, DrsGameType::SWBG]
+        .iter()
+        .cloned()
+        .find(|&game_type| game_type.header_len() == header_len);
+    let game_type = match game_type {
+        Some(game_type) => game_type,
+        None => {
+            let reason = format!("unexpected header length {}", header_len);
+            return Err(ErrorKind::InvalidCache(reason).into());
+        }
+    };
+
+    let mut bytes = vec![0u8; header_len as usize];
+    cache.read_exact(&mut bytes)?;
+    let copyright_len = DrsHeader::new(game_type).copyright_info.encoded_len();
+    let mut header = DrsHeader::with_copyright_bytes(game_type, &bytes[..copyright_len])?;
+    let mut rest = &bytes[copyright_len..];
+    rest.read_exact(&mut header.file_version)?;
+    rest.read_exact(&mut header.file_type)?;
+    header.table_count = rest.read_u32::<LittleEndian>()?;
+    header.file_offset = rest.read_u32::<LittleEndian>()?;
+    Ok(header)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{make_test_archive, write_temp_archive};
+
+    fn cached(drs_file: &DrsFile) -> Vec<u8> {
+        let mut cache = Vec::new();
+        drs_file.entries_to_cache(&mut cache).unwrap();
+        cache
+    }
+
+    #[test]
+    fn cache_opens_archive_lazily() {
+        let bytes = make_test_archive(DrsGameType::AOE,
+                                      &[(DrsFileType::Slp, 1, b"one"), (DrsFileType::Wav, 7, b"RIFF")]);
+        let path = write_temp_archive("cache.drs", &bytes);
+        let cache = cached(&DrsFile::from_bytes(&bytes).unwrap());
+
+        let mut reader = DrsFile::entries_from_cache(&path, &cache[..]).unwrap();
+        assert_eq!(reader.num_files(), 2);
+        assert_eq!(reader.read_file(DrsFileType::Wav, 7).unwrap().unwrap(), b"RIFF");
+        assert_eq!(reader.read_file(DrsFileType::Slp, 1).unwrap().unwrap(), b"one");
+    }
+
+    #[test]
+    fn cache_with_wrong_table_count_is_rejected() {
+        let bytes = make_test_archive(DrsGameType::AOE,
+                                      &[(DrsFileType::Slp, 1, b"one"), (DrsFileType::Wav, 7, b"RIFF")]);
+        let mut drs_file = DrsFile::from_bytes(&bytes).unwrap();
+        drs_file.header.table_count = 5;
+        match read_cache(&cached(&drs_file)[..]) {
+            Err(Error(ErrorKind::InvalidCache(_), _)) => {},
+            other => panic!("expected InvalidCache, got {:?}", other.map(|_| ())),
+        }
+    }
+}
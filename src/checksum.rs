@@ -0,0 +1,55 @@
+// Chariot: An open source reimplementation of Age of Empires (1997)
+// Copyright (c) 2016 Kevin Fuller
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+//
+use crate::drs::{DrsFile, DrsLogicalTable};
+
+impl DrsLogicalTable {
+    /// The CRC-32 (IEEE, as used by zip and PNG) of the contents of the file at the given
+    /// position in the table. Panics if the index is out of range.
+    pub fn entry_crc32(&self, index: usize) -> u32 {
+        crc32fast::hash(&self.contents[index])
+    }
+}
+
+impl DrsFile {
+    /// The CRC-32 of the contents of the file with the given ID, found as with `find_file`,
+    /// or None if the archive doesn't contain it. Useful for spotting files whose bytes
+    /// changed between two versions of an archive.
+    pub fn file_crc32(&self, file_id: u32) -> Option<u32> {
+        self.find_file(file_id).map(|contents| crc32fast::hash(contents))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::drs::{DrsFile, DrsFileType, DrsGameType};
+    use crate::fixtures::make_test_archive;
+
+    #[test]
+    fn crc32_of_check_string() {
+        let bytes = make_test_archive(DrsGameType::AOE, &[(DrsFileType::Slp, 1, b"123456789"), (DrsFileType::Slp, 2, b"")]);
+        let drs_file = DrsFile::from_bytes(&bytes).unwrap();
+        assert_eq!(drs_file.file_crc32(1), Some(0xCBF43926));
+        assert_eq!(drs_file.file_crc32(2), Some(0));
+        assert_eq!(drs_file.file_crc32(3), None);
+        assert_eq!(drs_file.tables[0].entry_crc32(0), 0xCBF43926);
+    }
+}
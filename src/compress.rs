@@ -0,0 +1,58 @@
+// Chariot: An open source reimplementation of Age of Empires (1997)
+// Copyright (c) 2016 Kevin Fuller
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+//
+
+use crate::drs::{DrsFile, DrsFileType};
+use crate::error::*;
+
+use flate2::read::{GzDecoder, ZlibDecoder};
+
+use std::io::prelude::*;
+
+impl DrsFile {
+    /// Returns a copy of a file's contents, inflated if they start with a recognized
+    /// compression header, or None if the archive doesn't contain the file. Contents
+    /// without a recognized header are returned as-is.
+    ///
+    /// Recognized signatures:
+    /// - gzip: the magic bytes `1F 8B` followed by the deflate method byte `08`.
+    /// - zlib: a CMF byte of `78` (deflate with a 32K window) whose two-byte header is a
+    ///   multiple of 31, as the format requires (e.g. `78 01`, `78 9C`, `78 DA`).
+    ///
+    /// Fails if the contents have one of these headers but don't inflate cleanly.
+    pub fn get_decompressed(&self, file_type: DrsFileType, file_id: u32) -> Result<Option<Vec<u8>>> {
+        let contents = match self.find_table(file_type).and_then(|t| t.find_file_contents(file_id)) {
+            Some(contents) => contents,
+            None => return Ok(None),
+        };
+
+        let mut decompressed = Vec::new();
+        if contents.starts_with(&[0x1F, 0x8B, 0x08]) {
+            GzDecoder::new(&contents[..]).read_to_end(&mut decompressed)?;
+        } else if contents.len() >= 2 && contents[0] == 0x78 &&
+                  (contents[0] as u16 * 256 + contents[1] as u16) % 31 == 0 {
+            ZlibDecoder::new(&contents[..]).read_to_end(&mut decompressed)?;
+        } else {
+            decompressed.extend_from_slice(contents);
+        }
+        Ok(Some(decompressed))
+    }
+}
@@ -0,0 +1,63 @@
+// Chariot: An open source reimplementation of Age of Empires (1997)
+// Copyright (c) 2016 Kevin Fuller
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+//
+
+use crate::drs::DrsFile;
+use crate::error::*;
+
+use std::io::prelude::*;
+
+impl DrsFile {
+    /// Writes the entry metadata as CSV: a header row, then one row per file in archive
+    /// order with its type (as the extension returned by `DrsFileType::tagged_extension`), file ID,
+    /// offset, and size. None of the fields can contain commas or quotes, so nothing is quoted.
+    pub fn write_csv<W: Write>(&self, mut out: W) -> Result<()> {
+        writeln!(out, "type,file_id,file_offset,file_size")?;
+        for table in &self.tables {
+            for entry in &table.entries {
+                writeln!(out,
+                         "{},{},{},{}",
+                         table.header.file_type.tagged_extension(),
+                         entry.file_id,
+                         entry.file_offset,
+                         entry.file_size)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::drs::{DrsFile, DrsGameType};
+    use crate::fixtures::make_hand_built_archive;
+
+    #[test]
+    fn write_csv_lists_every_entry() {
+        let drs_file = DrsFile::from_bytes(&make_hand_built_archive(DrsGameType::AOE)).unwrap();
+        let mut out = Vec::new();
+        drs_file.write_csv(&mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(),
+                   "type,file_id,file_offset,file_size\n\
+                    bin,50500,112,7\n\
+                    slp,1,119,3\n");
+    }
+}
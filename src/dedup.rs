@@ -0,0 +1,64 @@
+// Chariot: An open source reimplementation of Age of Empires (1997)
+// Copyright (c) 2016 Kevin Fuller
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+//
+
+use crate::drs::{DrsFile, DrsFileType};
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+
+impl DrsFile {
+    /// Groups the files of the whole archive whose contents are byte-for-byte identical,
+    /// regardless of which table they're in. Each group has at least two files, in archive
+    /// order, and groups are ordered by their first file.
+    ///
+    /// Files are bucketed by length and the standard library's `DefaultHasher` (SipHash)
+    /// of their contents; the hash is only used to find candidates, and files are only
+    /// grouped after their bytes have been compared.
+    pub fn duplicate_groups(&self) -> Vec<Vec<(DrsFileType, u32)>> {
+        // Each group is the contents its files share along with the files themselves, and
+        // each (length, hash) bucket holds the indices of the groups that fall into it.
+        let mut buckets: HashMap<(usize, u64), Vec<usize>> = HashMap::new();
+        let mut groups: Vec<(&[u8], Vec<_>)> = Vec::new();
+        for table in &self.tables {
+            for (entry, contents) in table.entries.iter().zip(&table.contents) {
+                let mut hasher = DefaultHasher::new();
+                contents.hash(&mut hasher);
+                let bucket = buckets.entry((contents.len(), hasher.finish())).or_default();
+
+                let key = (table.header.file_type, entry.file_id);
+                match bucket.iter().find(|&&group| groups[group].0 == &contents[..]) {
+                    Some(&group) => groups[group].1.push(key),
+                    None => {
+                        bucket.push(groups.len());
+                        groups.push((&contents[..], vec![key]));
+                    }
+                }
+            }
+        }
+
+        groups.into_iter()
+            .map(|(_, files)| files)
+            .filter(|files| files.len() > 1)
+            .collect()
+    }
+}
@@ -0,0 +1,90 @@
+// Chariot: An open source reimplementation of Age of Empires (1997)
+// Copyright (c) 2016 Kevin Fuller
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+//
+
+use crate::drs::{DrsFile, DrsFileContents, DrsFileType};
+
+/// The differences between two archives, as (file type, file ID) pairs. Files are compared
+/// from the perspective of the first archive: `added` files only exist in the second archive,
+/// `removed` files only exist in the first, and `modified` files exist in both with differing
+/// contents.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct DrsDiff {
+    pub added: Vec<(DrsFileType, u32)>,
+    pub removed: Vec<(DrsFileType, u32)>,
+    pub modified: Vec<(DrsFileType, u32)>,
+}
+
+impl DrsDiff {
+    pub fn new() -> DrsDiff {
+        Default::default()
+    }
+
+    /// True if the two archives contain the same files with the same contents.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.modified.is_empty()
+    }
+}
+
+impl DrsFile {
+    /// Compares this archive against another one that's already in memory, comparing the
+    /// contents of files present in both. Each list is in archive order, so files of the
+    /// same type are grouped together.
+    pub fn diff(&self, other: &DrsFile) -> DrsDiff {
+        let mut diff = DrsDiff::new();
+        for file in self.iter_files() {
+            let key = (file.file_type, file.file_id);
+            match find_contents(other, key) {
+                None => diff.removed.push(key),
+                Some(contents) if &contents[..] != file.contents => diff.modified.push(key),
+                Some(_) => {},
+            }
+        }
+        for file in other.iter_files() {
+            if find_contents(self, (file.file_type, file.file_id)).is_none() {
+                diff.added.push((file.file_type, file.file_id));
+            }
+        }
+        diff
+    }
+}
+
+fn find_contents(drs_file: &DrsFile, (file_type, file_id): (DrsFileType, u32)) -> Option<&DrsFileContents> {
+    drs_file.find_table(file_type).and_then(|table| table.find_file_contents(file_id))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::drs::{DrsFile, DrsFileType, DrsGameType};
+    use crate::fixtures::make_test_archive;
+
+    #[test]
+    fn diff_finds_changed_files() {
+        let old = make_test_archive(DrsGameType::AOE,
+                                    &[(DrsFileType::Slp, 1, b"one"), (DrsFileType::Slp, 2, b"two")]);
+        let new = make_test_archive(DrsGameType::AOE,
+                                    &[(DrsFileType::Slp, 1, b"ONE"), (DrsFileType::Slp, 3, b"three")]);
+        let diff = DrsFile::from_bytes(&old).unwrap().diff(&DrsFile::from_bytes(&new).unwrap());
+        assert_eq!(diff.modified, vec![(DrsFileType::Slp, 1)]);
+        assert_eq!(diff.removed, vec![(DrsFileType::Slp, 2)]);
+        assert_eq!(diff.added, vec![(DrsFileType::Slp, 3)]);
+    }
+}
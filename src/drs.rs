@@ -20,18 +20,29 @@
 // SOFTWARE.
 //
 
-use error::*;
+use crate::error::*;
 
-use chariot_io_tools::ReadExt;
+use chariot_io_tools::byteorder::{self, LittleEndian};
+use chariot_io_tools::{ReadExt, WriteExt};
 
 use either::Either;
 
-use std::collections::HashMap;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+use std::fmt;
+use std::mem;
 use std::fs::File;
 use std::io::prelude::*;
+use std::io::Cursor;
 use std::path::Path;
+use std::str;
 use std::io::Seek;
 use std::io::SeekFrom;
+use std::ops::Range;
 
 const EXPECTED_AOE_COPYRIGHT: &'static str = "Copyright (c) 1997 Ensemble Studios.\u{1A}";
 const EXPECTED_AOE_VERSION: &'static str = "1.00";
@@ -49,8 +60,70 @@ const SWBG_COPYRIGHT_LEN: usize = 60;
 type SwbgCopyright = [u8; SWBG_COPYRIGHT_LEN];
 const SWBG_COPYRIGHT_EMPTY: SwbgCopyright = [0u8; SWBG_COPYRIGHT_LEN];
 
+/// Size in bytes of a table header on disk.
+pub(crate) const TABLE_HEADER_LEN: u64 = 12;
+/// Size in bytes of a table entry on disk, with the standard 32-bit offset and size.
+pub(crate) const TABLE_ENTRY_LEN: u64 = 12;
+
 type DrsCopyrightType = Either<AoeCopyright, SwbgCopyright>;
 
+/// The copyright block at the start of a DRS header, whose length depends on the game.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum DrsCopyright {
+    /// Age of Empires (40 bytes)
+    Aoe(AoeCopyright),
+    /// Star Wars Galactic Battlegrounds (60 bytes)
+    Swbg(SwbgCopyright),
+}
+
+impl DrsCopyright {
+    pub fn game_type(&self) -> DrsGameType {
+        match *self {
+            DrsCopyright::Aoe(_) => DrsGameType::AOE,
+            DrsCopyright::Swbg(_) => DrsGameType::SWBG,
+        }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        match *self {
+            DrsCopyright::Aoe(ref bytes) => &bytes[..],
+            DrsCopyright::Swbg(ref bytes) => &bytes[..],
+        }
+    }
+
+    /// The number of bytes the copyright occupies on disk.
+    pub fn encoded_len(&self) -> usize {
+        self.as_bytes().len()
+    }
+
+    /// The copyright text up to the first null byte, with trailing whitespace and control
+    /// bytes (such as the 0x1A that ends the AOE copyright) trimmed off and any invalid
+    /// UTF-8 replaced.
+    pub fn decoded(&self) -> Cow<'_, str> {
+        decode_field(self.as_bytes())
+    }
+}
+
+impl From<DrsCopyrightType> for DrsCopyright {
+    fn from(copyright: DrsCopyrightType) -> DrsCopyright {
+        match copyright {
+            Either::Left(bytes) => DrsCopyright::Aoe(bytes),
+            Either::Right(bytes) => DrsCopyright::Swbg(bytes),
+        }
+    }
+}
+
+impl From<DrsCopyright> for DrsCopyrightType {
+    fn from(copyright: DrsCopyright) -> DrsCopyrightType {
+        match copyright {
+            DrsCopyright::Aoe(bytes) => Either::Left(bytes),
+            DrsCopyright::Swbg(bytes) => Either::Right(bytes),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum DrsGameType {
     /// Age of Empires
     AOE,
@@ -58,20 +131,60 @@ pub enum DrsGameType {
     SWBG,
 }
 
+impl DrsGameType {
+    /// The size of this game's archive header on disk: the copyright (40 bytes for AOE, 60
+    /// for SWBG), the 4-byte version, the 12-byte type, and the table count and file offset
+    /// as u32s. That's 64 bytes for AOE and 84 for SWBG.
+    pub fn header_len(&self) -> u64 {
+        let copyright_len = match *self {
+            DrsGameType::AOE => AOE_COPYRIGHT_LEN,
+            DrsGameType::SWBG => SWBG_COPYRIGHT_LEN,
+        };
+        (copyright_len + 4 + 12 + 4 + 4) as u64
+    }
+}
+
+/// The contents of a `DrsHeader` with its fixed-size string fields decoded, for storing
+/// in an index of archives rather than writing back out.
+#[derive(Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct DrsHeaderMetadata {
+    pub game_type: DrsGameType,
+    pub copyright: String,
+    pub file_version: String,
+    pub file_type: String,
+    pub table_count: u32,
+    pub file_offset: u32,
+}
+
+#[derive(PartialEq, Eq)]
 pub struct DrsHeader {
-    pub copyright_info: DrsCopyrightType,
+    pub copyright_info: DrsCopyright,
     pub file_version: [u8; 4],
     pub file_type: [u8; 12],
     pub table_count: u32,
     pub file_offset: u32,
 }
 
+/// Shows the header strings with their padding and terminators trimmed off.
+impl fmt::Debug for DrsHeader {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("DrsHeader")
+            .field("copyright_info", &self.copyright_str())
+            .field("file_version", &self.version_str())
+            .field("file_type", &self.file_type_str())
+            .field("table_count", &self.table_count)
+            .field("file_offset", &self.file_offset)
+            .finish()
+    }
+}
+
 impl DrsHeader {
     /// Notes:
-    /// - copyright_info defaults to Either::Left (which is AOE)
+    /// - copyright_info defaults to DrsCopyright::Aoe
     pub fn empty() -> DrsHeader {
         DrsHeader {
-            copyright_info: Either::Left(AOE_COPYRIGHT_EMPTY),
+            copyright_info: DrsCopyright::Aoe(AOE_COPYRIGHT_EMPTY),
             file_version: [0u8; 4],
             file_type: [0u8; 12],
             table_count: 0,
@@ -79,61 +192,212 @@ impl DrsHeader {
         }
     }
 
+    /// Creates a header carrying the copyright, version, and type strings that the given
+    /// game expects, so that an archive written with it will pass validation.
+    pub fn new(game_type: DrsGameType) -> DrsHeader {
+        let mut header = DrsHeader::empty();
+        match game_type {
+            DrsGameType::AOE => {
+                let mut copyright = AOE_COPYRIGHT_EMPTY;
+                copy_str(&mut copyright, EXPECTED_AOE_COPYRIGHT);
+                header.copyright_info = DrsCopyright::Aoe(copyright);
+                copy_str(&mut header.file_version, EXPECTED_AOE_VERSION);
+                copy_str(&mut header.file_type, EXPECTED_AOE_TYPE);
+            },
+            DrsGameType::SWBG => {
+                let mut copyright = SWBG_COPYRIGHT_EMPTY;
+                copy_str(&mut copyright, EXPECTED_SWBG_COPYRIGHT);
+                header.copyright_info = DrsCopyright::Swbg(copyright);
+                copy_str(&mut header.file_version, EXPECTED_SWBG_VERSION);
+                copy_str(&mut header.file_type, EXPECTED_SWBG_TYPE);
+            }
+        }
+        header
+    }
+
+    /// Like `new`, but uses the given copyright block verbatim instead of the standard one,
+    /// so an exact copyright can be carried over from another archive. The length must be
+    /// what the game expects (40 bytes for AOE, 60 bytes for SWBG).
+    pub fn with_copyright_bytes(game_type: DrsGameType, bytes: &[u8]) -> Result<DrsHeader> {
+        let mut header = DrsHeader::new(game_type);
+        let expected = header.copyright_info.encoded_len();
+        if bytes.len() != expected {
+            return Err(ErrorKind::InvalidCopyrightLength(expected, bytes.len()).into());
+        }
+        match header.copyright_info {
+            DrsCopyright::Aoe(ref mut copyright) => copyright.copy_from_slice(bytes),
+            DrsCopyright::Swbg(ref mut copyright) => copyright.copy_from_slice(bytes),
+        }
+        Ok(header)
+    }
+
     pub fn game_type(&self) -> DrsGameType {
-        match self.copyright_info {
-            Either::Left(_) => DrsGameType::AOE,
-            Either::Right(_) => DrsGameType::SWBG,
+        self.copyright_info.game_type()
+    }
+
+    /// The copyright string, decoded as by `DrsCopyright::decoded`. Invalid UTF-8 is replaced
+    /// rather than rejected, so this works on any header, validated or not.
+    pub fn copyright_str(&self) -> Cow<'_, str> {
+        self.copyright_info.decoded()
+    }
+
+    /// The version string, trimmed and decoded like `copyright_str`.
+    pub fn version_str(&self) -> Cow<'_, str> {
+        decode_field(&self.file_version)
+    }
+
+    /// The file type string, trimmed and decoded like `copyright_str`.
+    pub fn file_type_str(&self) -> Cow<'_, str> {
+        decode_field(&self.file_type)
+    }
+
+    /// The header's fields with its strings decoded as by `copyright_str` and friends.
+    pub fn metadata(&self) -> DrsHeaderMetadata {
+        DrsHeaderMetadata {
+            game_type: self.game_type(),
+            copyright: self.copyright_info.decoded().into_owned(),
+            file_version: decode_field(&self.file_version).into_owned(),
+            file_type: decode_field(&self.file_type).into_owned(),
+            table_count: self.table_count,
+            file_offset: self.file_offset,
+        }
+    }
+
+    /// Parses `file_version` as "X.YZ" into (X, YZ), so "1.00" is (1, 0) and "2.05" is
+    /// (2, 5). The minor version must be exactly two digits, so "1.5" (which would
+    /// otherwise read the same as "1.05") is rejected. Trailing null bytes are ignored.
+    /// Returns None if the field isn't a run of decimal digits, a dot, and two more digits.
+    pub fn version(&self) -> Option<(u8, u8)> {
+        let end = self.file_version.iter().position(|&b| b == 0).unwrap_or(self.file_version.len());
+        let version = str::from_utf8(&self.file_version[..end]).ok()?;
+        let dot = version.find('.')?;
+        let parse = |digits: &str| -> Option<u8> {
+            if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+                return None;
+            }
+            digits.parse().ok()
+        };
+        let minor = &version[dot + 1..];
+        if minor.len() != 2 {
+            return None;
         }
+        Some((parse(&version[..dot])?, parse(minor)?))
     }
 
-    // TODO: Implement writing
+    /// Checks that the copyright variant and the strings agree on the game: an `Aoe`
+    /// copyright must come with the AOE copyright, version, and type strings, and a `Swbg`
+    /// copyright with the SWBG ones. Headers produced by `read_from_file` and `new` always
+    /// pass; this guards against the two drifting apart elsewhere.
+    pub fn self_check(&self) -> Result<()> {
+        let (copyright, version, file_type) = match self.copyright_info {
+            DrsCopyright::Aoe(_) => (EXPECTED_AOE_COPYRIGHT, EXPECTED_AOE_VERSION, EXPECTED_AOE_TYPE),
+            DrsCopyright::Swbg(_) => (EXPECTED_SWBG_COPYRIGHT, EXPECTED_SWBG_VERSION, EXPECTED_SWBG_TYPE),
+        };
+        let fields = [("copyright", self.copyright_info.as_bytes(), copyright),
+                      ("file version", &self.file_version[..], version),
+                      ("file type", &self.file_type[..], file_type)];
+        for &(field, bytes, expected) in &fields {
+            if !matches_str(bytes, expected.as_bytes()) {
+                return Err(ErrorKind::InconsistentHeader(self.game_type(), field).into());
+            }
+        }
+        Ok(())
+    }
+
+    /// The number of bytes this header occupies on disk, which is where the table headers
+    /// start: `header_len` of its game type.
+    pub fn serialized_len(&self) -> u64 {
+        self.game_type().header_len()
+    }
+
+    /// Writes the header as-is; the table count and file offset aren't recomputed. That's
+    /// the copyright (40 bytes for AOE, 60 for SWBG), the 4-byte version, the 12-byte type,
+    /// and then the two counts as little-endian u32s, so `read_from` gives back an equal
+    /// header. Use `new` rather than `empty` for a header whose strings the game accepts.
+    pub fn write_to<W: Write>(&self, out: &mut W) -> Result<()> {
+        out.write_all(self.copyright_info.as_bytes())?;
+        out.write_all(&self.file_version)?;
+        out.write_all(&self.file_type)?;
+        out.write_u32(self.table_count)?;
+        out.write_u32(self.file_offset)?;
+        Ok(())
+    }
 
     pub fn read_from_file(file: &mut File, file_name: &Path) -> Result<DrsHeader> {
-        file.seek(SeekFrom::Start(64))?;
-        let mut type_str_buf = [0u8; 4];
-        try!(file.read_exact(&mut type_str_buf));
-        file.seek(SeekFrom::Start(0))?;
-        let type_str = ::std::str::from_utf8(&type_str_buf[..]).expect(&format!("Non-UTF8 file type: {:?}", type_str_buf));
+        DrsHeader::read_from(file, file_name)
+    }
 
-        let game_type = if type_str.trim() == "swbg" {
-            DrsGameType::SWBG
-        } else {
-            DrsGameType::AOE
+    /// Reads and validates the header from the start of any seekable reader. The file name
+    /// is only used in error messages; nothing is opened. Header strings are compared as raw
+    /// bytes, so arbitrary input never panics: anything that isn't a DRS header, including
+    /// non-UTF-8 garbage, fails with `InvalidDrs`, or with `TooSmall` if it's shorter than
+    /// any header.
+    pub fn read_from<R: Read + Seek>(reader: &mut R, file_name: &Path) -> Result<DrsHeader> {
+        DrsHeader::read_with_options(reader, file_name, &DrsReadOptions::default())
+    }
+
+    /// Reads the header, validating its strings as the options ask.
+    pub(crate) fn read_with_options<R: Read + Seek>(file: &mut R,
+                                                    file_name: &Path,
+                                                    options: &DrsReadOptions)
+                                                    -> Result<DrsHeader> {
+        // Without this, a truncated header fails with an error about whichever field is cut off.
+        let file_len = stream_len(file)?;
+        let check_fits = |game_type: DrsGameType| -> Result<()> {
+            if file_len < game_type.header_len() {
+                return Err(ErrorKind::TooSmall(file_name.into(), file_len).into());
+            }
+            Ok(())
+        };
+        check_fits(options.game_type.unwrap_or(DrsGameType::AOE))?;
+
+        let normalize = options.normalize_header_strings;
+        let game_type = match options.game_type {
+            Some(game_type) => game_type,
+            None => {
+                match detect_header_layout(file, file_name, normalize) {
+                    Ok(game_type) => game_type,
+                    Err(_) if !options.validate_header => guess_header_layout(file)?,
+                    Err(err) => return Err(err),
+                }
+            },
         };
+        check_fits(game_type)?;
+        file.seek(SeekFrom::Start(0))?;
 
         let copyright_info = match game_type {
             DrsGameType::AOE => {
                 let mut buf = AOE_COPYRIGHT_EMPTY;
-                try!(file.read_exact(&mut buf));
-                Either::Left(buf)
+                read_exact_at(file, &mut buf, "copyright")?;
+                DrsCopyright::Aoe(buf)
             },
             DrsGameType::SWBG => {
                 let mut buf = SWBG_COPYRIGHT_EMPTY;
-                try!(file.read_exact(&mut buf));
-                Either::Right(buf)
+                read_exact_at(file, &mut buf, "copyright")?;
+                DrsCopyright::Swbg(buf)
             }
         };
 
         let mut file_version = [0u8; 4];
-        try!(file.read_exact(&mut file_version));
+        read_exact_at(file, &mut file_version, "file version")?;
 
         let mut file_type = [0u8; 12];
-        try!(file.read_exact(&mut file_type));
+        read_exact_at(file, &mut file_type, "file type")?;
 
-        let table_count = try!(file.read_u32());
-        let file_offset = try!(file.read_u32());
+        let table_count = read_u32_at(file, "table count")?;
+        let file_offset = read_u32_at(file, "file offset")?;
 
-        match game_type {
-            DrsGameType::AOE => {
-                try!(validate_str(file_name, &copyright_info.left().unwrap()[..], EXPECTED_AOE_COPYRIGHT));
-                try!(validate_str(file_name, &file_version[..], EXPECTED_AOE_VERSION));
-                try!(validate_str(file_name, &file_type[..], EXPECTED_AOE_TYPE));
-            },
-            DrsGameType::SWBG => {
-                try!(validate_str(file_name, &copyright_info.right().unwrap()[..], EXPECTED_SWBG_COPYRIGHT));
-                try!(validate_str(file_name, &file_version[..], EXPECTED_SWBG_VERSION));
-                try!(validate_str(file_name, &file_type[..], EXPECTED_SWBG_TYPE));
+        if options.validate_header {
+            let (expected_copyright, expected_version, expected_type) = match game_type {
+                DrsGameType::AOE => (EXPECTED_AOE_COPYRIGHT, EXPECTED_AOE_VERSION, EXPECTED_AOE_TYPE),
+                DrsGameType::SWBG => (EXPECTED_SWBG_COPYRIGHT, EXPECTED_SWBG_VERSION, EXPECTED_SWBG_TYPE),
+            };
+            // Detecting the layout checks the copyright and file type, but forcing it doesn't.
+            if options.game_type.is_some() {
+                validate_str(file_name, copyright_info.as_bytes(), expected_copyright, normalize)?;
+                validate_str(file_name, &file_type[..], expected_type, normalize)?;
             }
+            validate_str(file_name, &file_version[..], expected_version, normalize)?;
         }
 
         let header = DrsHeader {
@@ -149,7 +413,8 @@ impl DrsHeader {
 }
 
 /// DRS supported file types.
-#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum DrsFileType {
     /// "Binary" denotes several different kinds of files used by Age of Empires
     /// that are not graphics or sound (even if they're text files). For example, palettes
@@ -161,34 +426,111 @@ pub enum DrsFileType {
     Shp,
     /// Typical WAV audio files.
     Wav,
+    /// A table whose type tag isn't one of the above, kept with its raw tag when an
+    /// `UnknownTypeHandler` asks for it with `UnknownTypeAction::AsOther`. Such tables are
+    /// written back out with the same tag, so they survive a round trip.
+    Unknown(u32),
 }
 
-// TODO: Move to using TryFrom when it becomes generally available in Rust
-impl From<u32> for DrsFileType {
-    fn from(binary_val: u32) -> Self {
+impl DrsFileType {
+    /// The raw little-endian tag identifying this type in a table header.
+    pub fn to_u32(&self) -> u32 {
+        match *self {
+            DrsFileType::Binary => 0x62696E61,
+            DrsFileType::Slp => 0x736C7020,
+            DrsFileType::Shp => 0x73687020,
+            DrsFileType::Wav => 0x77617620,
+            DrsFileType::Unknown(tag) => tag,
+        }
+    }
+
+    /// The file extension conventionally used for files of this type. Unknown types are
+    /// opaque data, so they get "bin" like Binary files; see `tagged_extension` for an
+    /// extension that tells them apart.
+    pub fn extension(&self) -> &'static str {
+        match *self {
+            DrsFileType::Binary => "bin",
+            DrsFileType::Slp => "slp",
+            DrsFileType::Shp => "shp",
+            DrsFileType::Wav => "wav",
+            DrsFileType::Unknown(_) => "bin",
+        }
+    }
+
+    /// The extension given to files of this type when they're extracted. This is the same
+    /// as `extension` except for unknown types, which get "x" followed by their tag in hex
+    /// (i.e., "x64636261") to keep them apart from each other and from the known types.
+    pub fn tagged_extension(&self) -> Cow<'static, str> {
+        match *self {
+            DrsFileType::Unknown(tag) => format!("x{:08x}", tag).into(),
+            _ => self.extension().into(),
+        }
+    }
+
+    /// Maps a file extension (as returned by `extension` or `tagged_extension`) back to its
+    /// file type. The comparison is case-insensitive; None is returned for unrecognized
+    /// extensions.
+    pub fn from_extension(extension: &str) -> Option<DrsFileType> {
+        let extension = extension.to_lowercase();
+        match extension.as_str() {
+            "bin" => Some(DrsFileType::Binary),
+            "slp" => Some(DrsFileType::Slp),
+            "shp" => Some(DrsFileType::Shp),
+            "wav" => Some(DrsFileType::Wav),
+            _ if extension.len() == 9 && extension.starts_with('x') &&
+                 extension[1..].bytes().all(|b| b.is_ascii_hexdigit()) => {
+                let tag = u32::from_str_radix(&extension[1..], 16).ok()?;
+                Some(known_file_type(tag).unwrap_or(DrsFileType::Unknown(tag)))
+            },
+            _ => None,
+        }
+    }
+}
+
+impl TryFrom<u32> for DrsFileType {
+    type Error = Error;
+
+    /// Fails with `UnknownFileType`, carrying the raw value, for unrecognized tags.
+    fn try_from(binary_val: u32) -> Result<Self> {
         // It looks like the Age of Empires devs decided to store the file types as little endian
         // integers (probably for faster/easier deserialization and type lookup). For binary files,
         // they decided to use "bina", while for all of the other files, they used the file
         // extension with a space (i.e., "wav ").
-        match binary_val {
-            0x62696E61 => DrsFileType::Binary,
-            0x736C7020 => DrsFileType::Slp,
-            0x73687020 => DrsFileType::Shp,
-            0x77617620 => DrsFileType::Wav,
-            _ => {
-                panic!("unknown file type encountered in DRS archive: 0x{:X}",
-                       binary_val)
-            }
-        }
+        known_file_type(binary_val).ok_or_else(|| ErrorKind::UnknownFileType(binary_val).into())
+    }
+}
+
+impl From<DrsFileType> for u32 {
+    /// The raw tag, as returned by `to_u32`. `DrsFileType::try_from` maps it back.
+    fn from(file_type: DrsFileType) -> u32 {
+        file_type.to_u32()
+    }
+}
+
+pub(crate) fn known_file_type(binary_val: u32) -> Option<DrsFileType> {
+    match binary_val {
+        0x62696E61 => Some(DrsFileType::Binary),
+        0x736C7020 => Some(DrsFileType::Slp),
+        0x73687020 => Some(DrsFileType::Shp),
+        0x77617620 => Some(DrsFileType::Wav),
+        _ => None,
     }
 }
 
+#[derive(Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct DrsTableHeader {
     pub file_type: DrsFileType,
     pub table_offset: u32,
     pub file_count: u32,
 }
 
+impl Default for DrsTableHeader {
+    fn default() -> DrsTableHeader {
+        DrsTableHeader::new()
+    }
+}
+
 impl DrsTableHeader {
     pub fn new() -> DrsTableHeader {
         DrsTableHeader {
@@ -198,49 +540,106 @@ impl DrsTableHeader {
         }
     }
 
-    // TODO: Implement writing
+    pub fn write_to<W: Write>(&self, out: &mut W) -> Result<()> {
+        out.write_u32(self.file_type.to_u32())?;
+        out.write_u32(self.table_offset)?;
+        out.write_u32(self.file_count)?;
+        Ok(())
+    }
 
-    fn read_from_file<R: Read>(file: &mut R) -> Result<DrsTableHeader> {
+    /// Reads a table header, consulting the handler (if any) when the type tag isn't
+    /// recognized. Also returns false if the handler asked for the table to be skipped.
+    fn read_from_file<R: Read + Seek>(file: &mut R,
+                                      unknown_type_handler: Option<&mut UnknownTypeHandler>)
+                                      -> Result<(DrsTableHeader, bool)> {
         let mut header = DrsTableHeader::new();
+        let mut keep = true;
 
-        header.file_type = DrsFileType::from(try!(file.read_u32()));
-        header.table_offset = try!(file.read_u32());
-        header.file_count = try!(file.read_u32());
-        Ok(header)
+        let binary_val = read_u32_at(file, "table file type")?;
+        header.file_type = match DrsFileType::try_from(binary_val) {
+            Ok(file_type) => file_type,
+            Err(error) => {
+                let action = match unknown_type_handler {
+                    Some(handler) => handler(binary_val),
+                    None => UnknownTypeAction::Error,
+                };
+                match action {
+                    UnknownTypeAction::Skip => {
+                        keep = false;
+                        DrsFileType::Unknown(binary_val)
+                    }
+                    UnknownTypeAction::AsBinary => DrsFileType::Binary,
+                    UnknownTypeAction::AsOther => DrsFileType::Unknown(binary_val),
+                    UnknownTypeAction::Error => return Err(error),
+                }
+            }
+        };
+        header.table_offset = read_u32_at(file, "table offset")?;
+        header.file_count = read_u32_at(file, "table file count")?;
+        Ok((header, keep))
     }
 
     pub fn file_extension(&self) -> &'static str {
-        match self.file_type {
-            DrsFileType::Binary => "bin",
-            DrsFileType::Slp => "slp",
-            DrsFileType::Shp => "shp",
-            DrsFileType::Wav => "wav",
-        }
+        self.file_type.extension()
     }
 }
 
+#[derive(Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct DrsTableEntry {
     pub file_id: u32,
-    pub file_offset: u32,
-    pub file_size: u32,
+    pub file_offset: u64,
+    pub file_size: u64,
+}
+
+impl Default for DrsTableEntry {
+    fn default() -> DrsTableEntry {
+        DrsTableEntry::new()
+    }
 }
 
 impl DrsTableEntry {
     pub fn new() -> DrsTableEntry {
         DrsTableEntry {
             file_id: 0u32,
-            file_offset: 0u32,
-            file_size: 0u32,
+            file_offset: 0u64,
+            file_size: 0u64,
+        }
+    }
+
+    /// Fails with `TruncatedFile` unless the file's data lies within an archive of the
+    /// given length.
+    pub(crate) fn check_fits(&self, file_len: u64) -> Result<()> {
+        let available = file_len.saturating_sub(self.file_offset);
+        if self.file_size > available {
+            let (file_id, expected) = (self.file_id, self.file_size);
+            return Err(ErrorKind::TruncatedFile { file_id, expected, available }.into());
         }
+        Ok(())
     }
 
-    // TODO: Implement writing
+    /// Writes the entry in the standard layout, which fails with `ExceedsFormatLimit` if the
+    /// offset or size doesn't fit in 32 bits.
+    pub fn write_to<W: Write>(&self, out: &mut W) -> Result<()> {
+        out.write_u32(self.file_id)?;
+        out.write_u32(to_u32(self.file_offset)?)?;
+        out.write_u32(to_u32(self.file_size)?)?;
+        Ok(())
+    }
 
-    fn read_from_file<R: Read>(file: &mut R) -> Result<DrsTableEntry> {
+    fn read_from_file<R: Read + Seek>(file: &mut R, offset_width: OffsetWidth) -> Result<DrsTableEntry> {
         let mut entry = DrsTableEntry::new();
-        entry.file_id = try!(file.read_u32());
-        entry.file_offset = try!(file.read_u32());
-        entry.file_size = try!(file.read_u32());
+        entry.file_id = read_u32_at(file, "entry file id")?;
+        match offset_width {
+            OffsetWidth::Standard => {
+                entry.file_offset = read_u32_at(file, "entry file offset")? as u64;
+                entry.file_size = read_u32_at(file, "entry file size")? as u64;
+            }
+            OffsetWidth::Wide => {
+                entry.file_offset = read_u64_at(file, "entry file offset")?;
+                entry.file_size = read_u64_at(file, "entry file size")?;
+            }
+        }
         Ok(entry)
     }
 }
@@ -255,6 +654,34 @@ pub struct DrsLogicalTable {
     pub entries: Vec<DrsTableEntry>,
     pub contents: Vec<DrsFileContents>,
     index_map: HashMap<u32, usize>,
+    sorted_by_id: bool,
+}
+
+/// Shows the entries' IDs rather than the file contents, which can run to megabytes.
+impl fmt::Debug for DrsLogicalTable {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("DrsLogicalTable")
+            .field("file_type", &self.header.file_type)
+            .field("entry_count", &self.entries.len())
+            .field("file_ids", &self.file_ids())
+            .finish()
+    }
+}
+
+/// Tables are equal when they're of the same type and hold the same files, by ID and
+/// contents, in any order. Offsets and the order entries are stored in aren't compared.
+impl PartialEq for DrsLogicalTable {
+    fn eq(&self, other: &DrsLogicalTable) -> bool {
+        self.header.file_type == other.header.file_type && self.sorted_files() == other.sorted_files()
+    }
+}
+
+impl Eq for DrsLogicalTable {}
+
+impl Default for DrsLogicalTable {
+    fn default() -> DrsLogicalTable {
+        DrsLogicalTable::new()
+    }
 }
 
 impl DrsLogicalTable {
@@ -264,9 +691,50 @@ impl DrsLogicalTable {
             entries: Vec::new(),
             contents: Vec::new(),
             index_map: HashMap::new(),
+            sorted_by_id: true,
         }
     }
 
+    /// Assembles a table from its parts, checking that there's exactly one set of contents
+    /// per entry, that each entry's `file_size` matches its contents, and that no file ID
+    /// appears twice. The header's `file_count` is set from the entries, and the table is
+    /// ready for lookups.
+    pub fn from_parts(header: DrsTableHeader,
+                      entries: Vec<DrsTableEntry>,
+                      contents: Vec<DrsFileContents>)
+                      -> Result<DrsLogicalTable> {
+        if entries.len() != contents.len() {
+            let reason = format!("{} entries but {} files", entries.len(), contents.len());
+            return Err(ErrorKind::InvalidTableParts(reason).into());
+        }
+        for (entry, file_contents) in entries.iter().zip(&contents) {
+            if entry.file_size != file_contents.len() as u64 {
+                let reason = format!("file {} has a size of {} but {} bytes of contents",
+                                     entry.file_id,
+                                     entry.file_size,
+                                     file_contents.len());
+                return Err(ErrorKind::InvalidTableParts(reason).into());
+            }
+        }
+
+        let mut table = DrsLogicalTable {
+            header,
+            entries,
+            contents,
+            index_map: HashMap::new(),
+            sorted_by_id: false,
+        };
+        table.sorted_by_id = table.entries_sorted_by_id();
+        table.header.file_count = to_u32(table.entries.len() as u64)?;
+        for (index, entry) in table.entries.iter().enumerate() {
+            if table.index_map.insert(entry.file_id, index).is_some() {
+                let (file_type, file_id) = (table.header.file_type, entry.file_id);
+                return Err(ErrorKind::DuplicateFileId { file_type, file_id }.into());
+            }
+        }
+        Ok(table)
+    }
+
     /// All files present inside of a DRS archive are labeled with a 32-bit integer file ID.
     /// This method attempts to find a file by ID in the given table.
     pub fn find_file_contents(&self, file_id: u32) -> Option<&DrsFileContents> {
@@ -276,26 +744,306 @@ impl DrsLogicalTable {
         }
     }
 
-    fn populate_index_map(&mut self) {
+    /// Whether the table has a file with the given ID.
+    pub fn contains_file(&self, file_id: u32) -> bool {
+        self.index_map.contains_key(&file_id)
+    }
+
+    /// Finds the entry for a file by ID, for its offset and size, without touching its contents.
+    pub fn find_entry(&self, file_id: u32) -> Option<&DrsTableEntry> {
+        self.index_map.get(&file_id).map(|&index| &self.entries[index])
+    }
+
+    /// Iterates over the files whose IDs fall within `range`, in table order. When the
+    /// entries are sorted by ID, as they are in the original archives, the matching run is
+    /// found with a binary search; otherwise every entry is checked. Whether they're sorted
+    /// is worked out when the table is read or assembled and kept up to date as files are
+    /// added and removed, so like the other lookups it doesn't see changes made directly
+    /// to `entries`.
+    pub fn files_in_range(&self,
+                          range: Range<u32>)
+                          -> impl Iterator<Item = (&DrsTableEntry, &DrsFileContents)> + '_ {
+        if self.sorted_by_id {
+            let start = self.entries.partition_point(|e| e.file_id < range.start);
+            let end = self.entries.partition_point(|e| e.file_id < range.end).max(start);
+            Either::Left(self.entries[start..end].iter().zip(&self.contents[start..end]))
+        } else {
+            Either::Right(self.entries
+                .iter()
+                .zip(&self.contents)
+                .filter(move |(entry, _)| range.contains(&entry.file_id)))
+        }
+    }
+
+    /// Iterates over a file's contents in slices of at most `chunk_size` bytes, without
+    /// copying them. An empty file yields no chunks. Returns None if the table doesn't
+    /// contain the file, and panics if `chunk_size` is 0.
+    pub fn file_chunks(&self, file_id: u32, chunk_size: usize) -> Option<impl Iterator<Item = &[u8]> + '_> {
+        self.find_file_contents(file_id).map(|contents| contents.chunks(chunk_size))
+    }
+
+    /// True if ordering the entries by file ID also orders their data by offset, i.e. reading
+    /// files in ID order walks forward through the archive. Empty files may share an offset
+    /// with their neighbours without breaking the ordering.
+    pub fn is_physically_sorted(&self) -> bool {
+        let mut by_id: Vec<&DrsTableEntry> = self.entries.iter().collect();
+        by_id.sort_by_key(|entry| entry.file_id);
+        by_id.windows(2).all(|pair| pair[0].file_offset <= pair[1].file_offset)
+    }
+
+    /// True if more than one entry in the table has the same file ID. Such entries are all
+    /// kept in `entries` and `contents`, but `find_file_contents` only finds the last one;
+    /// use `find_all_file_contents` to get at the others.
+    pub fn has_duplicate_ids(&self) -> bool {
+        self.first_duplicate_id().is_some()
+    }
+
+    /// Each file's ID and contents, ordered by ID and then contents.
+    fn sorted_files(&self) -> Vec<(u32, &[u8])> {
+        let mut files: Vec<(u32, &[u8])> = self.entries
+            .iter()
+            .zip(&self.contents)
+            .map(|(entry, contents)| (entry.file_id, &contents[..]))
+            .collect();
+        files.sort();
+        files
+    }
+
+    fn first_duplicate_id(&self) -> Option<u32> {
+        let mut seen = HashSet::new();
+        self.entries.iter().map(|e| e.file_id).find(|&file_id| !seen.insert(file_id))
+    }
+
+    /// The contents of every file in the table with the given ID, in entry order. This is
+    /// only ever more than one file for tables where `has_duplicate_ids` is true.
+    pub fn find_all_file_contents(&self, file_id: u32) -> impl Iterator<Item = &DrsFileContents> + '_ {
+        self.entries
+            .iter()
+            .zip(&self.contents)
+            .filter(move |(entry, _)| entry.file_id == file_id)
+            .map(|(_, contents)| contents)
+    }
+
+    /// The IDs of the table's files in the order their entries appear, which isn't
+    /// necessarily sorted.
+    pub fn file_ids(&self) -> Vec<u32> {
+        self.entries.iter().map(|e| e.file_id).collect()
+    }
+
+    /// Appends a file to the end of the table and makes it available to lookups straight
+    /// away. Its offset is left at zero until the archive is written.
+    pub(crate) fn push_file(&mut self, file_id: u32, contents: DrsFileContents) -> Result<()> {
+        if self.index_map.contains_key(&file_id) {
+            let file_type = self.header.file_type;
+            return Err(ErrorKind::DuplicateFileId { file_type, file_id }.into());
+        }
+        let mut entry = DrsTableEntry::new();
+        entry.file_id = file_id;
+        entry.file_size = contents.len() as u64;
+        if let Some(last) = self.entries.last() {
+            self.sorted_by_id &= last.file_id <= file_id;
+        }
+        self.index_map.insert(file_id, self.entries.len());
+        self.entries.push(entry);
+        self.contents.push(contents);
+        self.header.file_count = to_u32(self.entries.len() as u64)?;
+        Ok(())
+    }
+
+    /// Takes a file out of the table, returning its contents, or None if the table
+    /// doesn't have it.
+    pub(crate) fn remove_file(&mut self, file_id: u32) -> Option<DrsFileContents> {
+        let index = *self.index_map.get(&file_id)?;
+        self.entries.remove(index);
+        let contents = self.contents.remove(index);
+        self.header.file_count = self.entries.len() as u32;
+        self.index_map.clear();
+        self.populate_index_map();
+        Some(contents)
+    }
+
+    /// Prepares the table for lookups by ID from its current entries.
+    pub(crate) fn populate_index_map(&mut self) {
         for i in 0..self.entries.len() {
             self.index_map.insert(self.entries[i].file_id, i);
         }
+        self.sorted_by_id = self.entries_sorted_by_id();
+    }
+
+    fn entries_sorted_by_id(&self) -> bool {
+        self.entries.windows(2).all(|pair| pair[0].file_id <= pair[1].file_id)
+    }
+}
+
+/// What to do with a table whose type tag isn't recognized.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum UnknownTypeAction {
+    /// Leave the table out of the `DrsFile`. Its entries are still read past, and the other
+    /// tables' offsets and the header's file offset are adjusted as if it had never been
+    /// there, so the result still passes `DrsFile::validate_integrity`.
+    Skip,
+    /// Treat the table as a Binary table.
+    AsBinary,
+    /// Keep the table as `DrsFileType::Unknown`, carrying the raw tag, so that it's written
+    /// back out unchanged.
+    AsOther,
+    /// Fail the read.
+    Error,
+}
+
+/// Called with the raw tag of each table whose type isn't recognized.
+pub type UnknownTypeHandler = Box<dyn FnMut(u32) -> UnknownTypeAction>;
+
+/// Options controlling how `DrsFile::read_from_file_with_options` reads an archive.
+pub struct DrsReadOptions {
+    /// Refuses to load archives whose files add up to more than this many bytes. The check
+    /// only uses the entry metadata, so it happens before any file contents are allocated,
+    /// which protects against untrusted archives claiming enormous files.
+    pub max_total_bytes: Option<u64>,
+    /// Accepts header strings that differ from the expected ones only in how they're
+    /// terminated or padded, e.g. a null instead of the SUB (0x1A) after the copyright, or
+    /// trailing spaces. Off by default, so the strings must match exactly.
+    pub normalize_header_strings: bool,
+    /// Checks the header's copyright, version, and type strings against the ones the games
+    /// write. On by default. When off, archives with nonstandard strings (as written by
+    /// some repacking tools) are still read, the game being picked by the layout the file
+    /// type appears in, and the strings as found are kept in the header for inspection.
+    pub validate_header: bool,
+    /// Reads the header as this game's instead of detecting the game from the strings in
+    /// it, for archives that detection gets wrong. The header is still validated against
+    /// that game's strings unless `validate_header` is off.
+    pub game_type: Option<DrsGameType>,
+    /// Fails with `DuplicateFileId` if a table has more than one file with the same ID.
+    /// Off by default, in which case every duplicate is kept and can be found with
+    /// `DrsLogicalTable::find_all_file_contents`, while plain lookups find the last one.
+    pub reject_duplicate_ids: bool,
+    /// Keeps a copy of the header's bytes (copyright, version, type, table count, and file
+    /// offset) exactly as read, available from `DrsFile::raw_header`, for comparing
+    /// against a known-good header byte for byte.
+    pub keep_raw_header: bool,
+    /// The width of the offset and size in each table entry.
+    pub offset_width: OffsetWidth,
+    /// Decides what happens to tables with unrecognized type tags. Without a handler
+    /// they're treated as `UnknownTypeAction::Error`.
+    pub unknown_type_handler: Option<UnknownTypeHandler>,
+}
+
+impl Default for DrsReadOptions {
+    fn default() -> DrsReadOptions {
+        DrsReadOptions {
+            max_total_bytes: None,
+            normalize_header_strings: false,
+            validate_header: true,
+            game_type: None,
+            reject_duplicate_ids: false,
+            keep_raw_header: false,
+            offset_width: OffsetWidth::Standard,
+            unknown_type_handler: None,
+        }
     }
 }
 
+impl fmt::Debug for DrsReadOptions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("DrsReadOptions")
+            .field("max_total_bytes", &self.max_total_bytes)
+            .field("normalize_header_strings", &self.normalize_header_strings)
+            .field("validate_header", &self.validate_header)
+            .field("game_type", &self.game_type)
+            .field("reject_duplicate_ids", &self.reject_duplicate_ids)
+            .field("keep_raw_header", &self.keep_raw_header)
+            .field("offset_width", &self.offset_width)
+            .field("unknown_type_handler", &self.unknown_type_handler.as_ref().map(|_| ".."))
+            .finish()
+    }
+}
+
+/// How wide the `file_offset` and `file_size` fields of a table entry are on disk.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+pub enum OffsetWidth {
+    /// 32-bit fields, as in every archive shipped with the games.
+    #[default]
+    Standard,
+    /// 64-bit fields, used by experimental variants for archives larger than 4GB. Entries
+    /// are 20 bytes rather than 12, and everything else is laid out as usual.
+    Wide,
+}
+
+impl OffsetWidth {
+    /// The size in bytes of a table entry on disk.
+    pub(crate) fn entry_len(self) -> u64 {
+        match self {
+            OffsetWidth::Standard => TABLE_ENTRY_LEN,
+            OffsetWidth::Wide => 20,
+        }
+    }
+}
+
+/// A file in an archive along with the type of the table it came from, as yielded by
+/// `DrsFile::iter_files`.
+#[derive(Debug, Clone, Copy)]
+pub struct DrsFileRef<'a> {
+    pub file_type: DrsFileType,
+    pub file_id: u32,
+    pub contents: &'a [u8],
+}
+
 pub struct DrsFile {
     pub header: DrsHeader,
     pub tables: Vec<DrsLogicalTable>,
+    build_tag: Option<String>,
+    raw_header: Option<Vec<u8>>,
 }
 
+impl fmt::Debug for DrsFile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("DrsFile")
+            .field("header", &self.header)
+            .field("tables", &self.tables)
+            .field("build_tag", &self.build_tag)
+            .field("raw_header_len", &self.raw_header.as_ref().map(|h| h.len()))
+            .finish()
+    }
+}
+
+/// Archives are equal when their headers have the same copyright, version, and type strings
+/// and they have equal tables of each type, so an archive read back after being written
+/// equals the original. Table order, the layout (including the header's table count and
+/// file offset), the build tag, and the copy of the header kept by
+/// `DrsReadOptions::keep_raw_header` aren't compared.
+impl PartialEq for DrsFile {
+    fn eq(&self, other: &DrsFile) -> bool {
+        let (header, other_header) = (&self.header, &other.header);
+        header.copyright_info == other_header.copyright_info && header.file_version == other_header.file_version &&
+        header.file_type == other_header.file_type && self.tables_by_type() == other.tables_by_type()
+    }
+}
+
+impl Eq for DrsFile {}
+
 impl DrsFile {
+    /// The tables ordered by type, keeping tables of the same type in archive order.
+    fn tables_by_type(&self) -> Vec<&DrsLogicalTable> {
+        let mut tables: Vec<&DrsLogicalTable> = self.tables.iter().collect();
+        tables.sort_by_key(|table| u32::from(table.header.file_type));
+        tables
+    }
+
     pub fn empty() -> DrsFile {
         DrsFile {
             header: DrsHeader::empty(),
             tables: Vec::new(),
+            build_tag: None,
+            raw_header: None,
         }
     }
 
+    /// The build tag stored in the archive's trailer by `DrsWriteOptions::build_tag`, if
+    /// it was read from a file that has one.
+    pub fn build_tag(&self) -> Option<String> {
+        self.build_tag.clone()
+    }
+
     /// DRS archives are partitioned into tables by file type. This method will
     /// attempt to find a table of the requested type, and return None if it doesn't exist.
     pub fn find_table(&self, file_type: DrsFileType) -> Option<&DrsLogicalTable> {
@@ -307,62 +1055,1041 @@ impl DrsFile {
         return None;
     }
 
+    /// The header of the first table of the given type, or None if there's no such table.
+    pub fn table_header(&self, file_type: DrsFileType) -> Option<&DrsTableHeader> {
+        self.find_table(file_type).map(|t| &t.header)
+    }
+
+    /// Finds a file by ID without knowing its type, searching tables in order and returning
+    /// the first match.
+    pub fn find_file(&self, file_id: u32) -> Option<&DrsFileContents> {
+        self.find_file_with_type(file_id).map(|(_, contents)| contents)
+    }
+
+    /// Like `find_file`, but also says which type of table the file was found in.
+    pub fn find_file_with_type(&self, file_id: u32) -> Option<(DrsFileType, &DrsFileContents)> {
+        self.tables
+            .iter()
+            .find_map(|table| table.find_file_contents(file_id).map(|contents| (table.header.file_type, contents)))
+    }
+
+    /// Whether any table has a file with the given ID, stopping at the first that does.
+    pub fn contains_file(&self, file_id: u32) -> bool {
+        self.tables.iter().any(|table| table.contains_file(file_id))
+    }
+
+    /// Like `find_file_with_type`, but returns the file's entry rather than its contents.
+    pub fn find_entry(&self, file_id: u32) -> Option<(DrsFileType, &DrsTableEntry)> {
+        self.tables
+            .iter()
+            .find_map(|table| table.find_entry(file_id).map(|entry| (table.header.file_type, entry)))
+    }
+
+    /// Whether the archive holds no files at all, either because it has no tables or
+    /// because every table is empty. Lookups on such an archive always come up empty.
+    pub fn is_empty(&self) -> bool {
+        self.tables.iter().all(|t| t.entries.is_empty())
+    }
+
+    /// The number of files in the archive across all tables.
+    pub fn total_file_count(&self) -> usize {
+        self.tables.iter().map(|t| t.entries.len()).sum()
+    }
+
+    /// The number of files in the table of the given type (the first one, if there are
+    /// several), or 0 if the archive has no such table.
+    pub fn file_count(&self, file_type: DrsFileType) -> usize {
+        self.find_table(file_type).map_or(0, |t| t.entries.len())
+    }
+
+    /// The IDs of every file in the archive, tables in order and then entries in order.
+    /// They aren't sorted, and an ID appears more than once if several tables use it;
+    /// callers that need a sorted or distinct list should sort and dedup the result.
+    /// Use `find_table(file_type)` and `DrsLogicalTable::file_ids` for one type's IDs.
+    pub fn file_ids(&self) -> Vec<u32> {
+        self.tables.iter().flat_map(|t| t.entries.iter().map(|e| e.file_id)).collect()
+    }
+
+    /// The set of file types that have at least one table in the archive.
+    pub fn distinct_types(&self) -> HashSet<DrsFileType> {
+        self.tables.iter().map(|t| t.header.file_type).collect()
+    }
+
+    /// True if more than one table shares a file type. Well-formed archives have one table
+    /// per type; when they don't, `find_table` only ever returns the first of them.
+    pub fn has_duplicate_type_tables(&self) -> bool {
+        self.distinct_types().len() < self.tables.len()
+    }
+
+    /// Iterates over the tables in order, each alongside its file type, such as to pick
+    /// out just the `Wav` tables with a `filter`.
+    pub fn tables(&self) -> impl Iterator<Item = (DrsFileType, &DrsLogicalTable)> {
+        self.tables.iter().map(|table| (table.header.file_type, table))
+    }
+
+    /// Iterates over every file in the archive, tables in order and then entries in order.
+    pub fn iter_files(&self) -> impl Iterator<Item = DrsFileRef<'_>> {
+        self.tables.iter().flat_map(|table| {
+            table.entries.iter().zip(&table.contents).map(move |(entry, contents)| {
+                DrsFileRef {
+                    file_type: table.header.file_type,
+                    file_id: entry.file_id,
+                    contents,
+                }
+            })
+        })
+    }
+
+    /// Calls `f` with the type, ID, and contents of every file in the archive, allowing
+    /// the contents to be modified in place. Each entry's `file_size` is updated afterwards
+    /// to match the length of its (possibly resized) contents.
+    pub fn for_each_file_mut<F: FnMut(DrsFileType, u32, &mut DrsFileContents)>(&mut self, mut f: F) {
+        for table in &mut self.tables {
+            let file_type = table.header.file_type;
+            for (entry, contents) in table.entries.iter_mut().zip(table.contents.iter_mut()) {
+                f(file_type, entry.file_id, contents);
+                entry.file_size = contents.len() as u64;
+            }
+        }
+    }
+
+    /// Adds a file to the first table of the given type, creating the table at the end of
+    /// the archive if there isn't one. The file can be looked up straight away; its offset
+    /// is only worked out when the archive is written. Fails with `DuplicateFileId` if the
+    /// table already has a file with that ID.
+    pub fn add_file(&mut self, file_type: DrsFileType, file_id: u32, contents: DrsFileContents) -> Result<()> {
+        let table_index = match self.tables.iter().position(|t| t.header.file_type == file_type) {
+            Some(table_index) => table_index,
+            None => {
+                let mut table = DrsLogicalTable::new();
+                table.header.file_type = file_type;
+                self.tables.push(table);
+                self.header.table_count = to_u32(self.tables.len() as u64)?;
+                self.tables.len() - 1
+            }
+        };
+        self.tables[table_index].push_file(file_id, contents)
+    }
+
+    /// Moves every file from `other` into this archive, each going into the first table of
+    /// its type as with `add_file`. Fails with `DuplicateFileId`, leaving this archive
+    /// untouched, if any file's type and ID are already taken.
+    pub fn merge(&mut self, other: DrsFile) -> Result<()> {
+        let mut incoming = HashSet::new();
+        for file in other.iter_files() {
+            let taken = self.find_table(file.file_type).is_some_and(|t| t.find_file_contents(file.file_id).is_some());
+            if taken || !incoming.insert((file.file_type, file.file_id)) {
+                let (file_type, file_id) = (file.file_type, file.file_id);
+                return Err(ErrorKind::DuplicateFileId { file_type, file_id }.into());
+            }
+        }
+
+        for table in other.tables {
+            for (entry, contents) in table.entries.into_iter().zip(table.contents) {
+                self.add_file(table.header.file_type, entry.file_id, contents)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Swaps in new contents for the file with the given ID, in the first table that has
+    /// it, and returns the old contents. The file keeps its table and its place in it, and
+    /// its `file_size` is updated to match. Fails with `FileIdNotFound` if no table has it.
+    pub fn replace_file(&mut self, file_id: u32, contents: DrsFileContents) -> Result<DrsFileContents> {
+        for table in &mut self.tables {
+            if let Some(&index) = table.index_map.get(&file_id) {
+                table.entries[index].file_size = contents.len() as u64;
+                return Ok(mem::replace(&mut table.contents[index], contents));
+            }
+        }
+        Err(ErrorKind::FileIdNotFound(file_id).into())
+    }
+
+    /// Removes the first file with the given ID, searching tables in order, and returns its
+    /// contents, or None if no table has it. A table left empty by the removal is dropped
+    /// so that it isn't written out.
+    pub fn remove_file(&mut self, file_id: u32) -> Option<DrsFileContents> {
+        let (table_index, contents) = self.tables
+            .iter_mut()
+            .enumerate()
+            .find_map(|(table_index, table)| table.remove_file(file_id).map(|contents| (table_index, contents)))?;
+        if self.tables[table_index].entries.is_empty() {
+            self.tables.remove(table_index);
+            self.header.table_count = self.tables.len() as u32;
+        }
+        Some(contents)
+    }
+
+    /// The header exactly as it appeared on disk, if the archive was read with
+    /// `DrsReadOptions::keep_raw_header`.
+    pub fn raw_header(&self) -> Option<&[u8]> {
+        self.raw_header.as_ref().map(|bytes| &bytes[..])
+    }
+
+    /// Checks that the header's table count matches the number of tables, and that each
+    /// table's file count matches its number of entries. Reading always produces
+    /// consistent counts, but they can drift once tables are edited by hand.
+    pub fn validate_counts(&self) -> Result<()> {
+        if self.header.table_count as usize != self.tables.len() {
+            return Err(ErrorKind::TableCountMismatch(self.header.table_count, self.tables.len()).into());
+        }
+        for (table_index, table) in self.tables.iter().enumerate() {
+            if table.header.file_count as usize != table.entries.len() {
+                let reason = format!("file count is {} but there are {} entries",
+                                     table.header.file_count,
+                                     table.entries.len());
+                return Err(ErrorKind::InconsistentTable(table_index, reason).into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that the archive's offsets describe a well-formed layout: each table's
+    /// `table_offset` points at where its entries sit (assuming the standard 12-byte
+    /// entries), every entry's size matches its contents, no two files overlap, no file
+    /// starts before the file data, and `header.file_offset` is where the file data starts.
+    /// Counts are checked as in `validate_counts`. The first problem found is reported,
+    /// naming the table and file involved.
+    pub fn validate_integrity(&self) -> Result<()> {
+        self.validate_counts()?;
+
+        let mut table_offset = self.header.serialized_len() + TABLE_HEADER_LEN * self.tables.len() as u64;
+        let mut files = Vec::new();
+        for (table_index, table) in self.tables.iter().enumerate() {
+            if table.header.table_offset as u64 != table_offset {
+                let reason = format!("its entries are at {} but its table offset is {}",
+                                     table_offset,
+                                     table.header.table_offset);
+                return Err(ErrorKind::InconsistentTable(table_index, reason).into());
+            }
+            table_offset += TABLE_ENTRY_LEN * table.entries.len() as u64;
+
+            for (entry, contents) in table.entries.iter().zip(&table.contents) {
+                if entry.file_size != contents.len() as u64 {
+                    let reason = format!("file {} has a size of {} but {} bytes of contents",
+                                         entry.file_id,
+                                         entry.file_size,
+                                         contents.len());
+                    return Err(ErrorKind::InconsistentTable(table_index, reason).into());
+                }
+                files.push((entry.file_offset, entry.file_size, table_index, entry.file_id));
+            }
+        }
+
+        let metadata_end = table_offset;
+        // Empty files have no data, so their offsets say nothing about where the data starts.
+        let data_start = files.iter().filter(|f| f.1 > 0).map(|f| f.0).min().unwrap_or(metadata_end);
+        if self.header.file_offset as u64 != data_start || data_start < metadata_end {
+            let reason = format!("file offset is {} but the metadata ends at {} and the file data starts at {}",
+                                 self.header.file_offset,
+                                 metadata_end,
+                                 data_start);
+            return Err(ErrorKind::ImplausibleHeader(reason).into());
+        }
+
+        // Empty files take up no space, so they can't overlap anything.
+        files.retain(|&(_, file_size, _, _)| file_size > 0);
+        files.sort_by_key(|&(file_offset, _, _, _)| file_offset);
+        for pair in files.windows(2) {
+            let (prev_offset, prev_size, _, prev_id) = pair[0];
+            let (file_offset, _, table_index, file_id) = pair[1];
+            // A file reaching past the end of the u64 range overlaps anything after it.
+            let overlaps = match prev_offset.checked_add(prev_size) {
+                Some(prev_end) => file_offset < prev_end,
+                None => true,
+            };
+            if overlaps {
+                let reason = format!("file {} at offset {} overlaps file {} at offset {}",
+                                     file_id,
+                                     file_offset,
+                                     prev_id,
+                                     prev_offset);
+                return Err(ErrorKind::InconsistentTable(table_index, reason).into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Describes anything about the archive that's legal but unusual enough to suggest a
+    /// problem, such as an archive with no tables, which the game's own archives never are.
+    /// Unlike `validate_integrity`, none of these stop the archive from being used.
+    pub fn integrity_warnings(&self) -> Vec<String> {
+        let mut warnings = Vec::new();
+        if self.tables.is_empty() {
+            warnings.push("the archive has no tables".to_string());
+        }
+        warnings
+    }
+
     /// Loads a DRS archive from the file system.
     pub fn read_from_file<P: AsRef<Path>>(file_name: P) -> Result<DrsFile> {
+        DrsFile::read_from_file_with_options(file_name, DrsReadOptions::default())
+    }
+
+    /// Loads a DRS archive from the file system as the given game's, rather than detecting
+    /// the game from the header. See `DrsReadOptions::game_type`.
+    pub fn read_from_file_as<P: AsRef<Path>>(file_name: P, game_type: DrsGameType) -> Result<DrsFile> {
+        let options = DrsReadOptions { game_type: Some(game_type), ..DrsReadOptions::default() };
+        DrsFile::read_from_file_with_options(file_name, options)
+    }
+
+    /// Loads a DRS archive from the file system, with control over how it's read.
+    pub fn read_from_file_with_options<P: AsRef<Path>>(file_name: P, options: DrsReadOptions) -> Result<DrsFile> {
         let file_name = file_name.as_ref();
-        let mut file = try!(File::open(file_name));
+        let mut file = File::open(file_name)?;
+        let mut drs_file = DrsFile::read_metadata_from(&mut file, file_name, options)?;
+        // With a real file the contents can be read with positioned reads, in parallel.
+        #[cfg(feature = "rayon")]
+        drs_file.read_contents_parallel(&file)?;
+        #[cfg(not(feature = "rayon"))]
+        drs_file.read_contents_from(&mut file)?;
+        Ok(drs_file)
+    }
+
+    /// Loads a DRS archive from any seekable reader, such as a `Cursor` over an in-memory
+    /// buffer. The archive must start at offset 0 of the reader. The file name is only
+    /// used in error messages.
+    pub fn read_from<R: Read + Seek>(reader: &mut R, file_name: &Path) -> Result<DrsFile> {
+        DrsFile::read_from_with_options(reader, file_name, DrsReadOptions::default())
+    }
+
+    /// Loads a DRS archive that's already in memory, such as one embedded with
+    /// `include_bytes!`. Errors that name a path use `<memory>`.
+    pub fn from_bytes(data: &[u8]) -> Result<DrsFile> {
+        DrsFile::read_from(&mut Cursor::new(data), Path::new("<memory>"))
+    }
+
+    /// Like `read_from`, with control over how the archive is read.
+    pub fn read_from_with_options<R: Read + Seek>(file: &mut R,
+                                                  file_name: &Path,
+                                                  options: DrsReadOptions)
+                                                  -> Result<DrsFile> {
+        let mut drs_file = DrsFile::read_metadata_from(file, file_name, options)?;
+        drs_file.read_contents_from(file)?;
+        Ok(drs_file)
+    }
 
+    /// Reads the header, table headers, and entries, leaving every table's contents empty.
+    pub(crate) fn read_metadata_from<R: Read + Seek>(file: &mut R,
+                                                     file_name: &Path,
+                                                     mut options: DrsReadOptions)
+                                                     -> Result<DrsFile> {
         let mut drs_file = DrsFile::empty();
-        drs_file.header = try!(DrsHeader::read_from_file(&mut file, file_name));
-        try!(DrsFile::read_table_headers(&mut file, &mut drs_file));
-        try!(DrsFile::read_file_entry_headers(&mut file, &mut drs_file));
-        try!(DrsFile::read_file_contents(&mut file, &mut drs_file));
+        drs_file.header = DrsHeader::read_with_options(file, file_name, &options)?;
+        if options.keep_raw_header {
+            let mut raw_header = vec![0u8; drs_file.header.serialized_len() as usize];
+            file.seek(SeekFrom::Start(0))?;
+            read_exact_at(file, &mut raw_header, "header")?;
+            drs_file.raw_header = Some(raw_header);
+        }
+        let (table_headers, keep) = DrsFile::read_table_headers(file,
+                                                                drs_file.header.table_count,
+                                                                options.unknown_type_handler.as_mut())?;
+        let table_entries = DrsFile::read_file_entry_headers(file, &table_headers, options.offset_width)?;
+        repair_file_offset(file, &mut drs_file.header, &table_headers, options.offset_width)?;
+        let skipped_any = keep.contains(&false);
+        for ((header, entries), keep) in table_headers.into_iter().zip(table_entries).zip(keep) {
+            if !keep {
+                continue;
+            }
+            let mut table = DrsLogicalTable::new();
+            table.header = header;
+            table.entries = entries;
+            table.sorted_by_id = table.entries_sorted_by_id();
+            drs_file.tables.push(table);
+        }
+        drs_file.header.table_count = drs_file.tables.len() as u32;
+        if skipped_any {
+            drs_file.lay_out_without_skipped_tables(options.offset_width.entry_len())?;
+        }
+        if let Some(limit) = options.max_total_bytes {
+            // Wide entries can claim sizes whose sum doesn't fit in a u64, which is over any limit.
+            let total = drs_file.tables
+                .iter()
+                .flat_map(|t| t.entries.iter())
+                .try_fold(0u64, |total, e| total.checked_add(e.file_size));
+            match total {
+                Some(total) if total <= limit => {},
+                total => return Err(ErrorKind::ArchiveTooLarge(total.unwrap_or(u64::MAX), limit).into()),
+            }
+        }
+        if options.reject_duplicate_ids {
+            for table in &drs_file.tables {
+                if let Some(file_id) = table.first_duplicate_id() {
+                    let file_type = table.header.file_type;
+                    return Err(ErrorKind::DuplicateFileId { file_type, file_id }.into());
+                }
+            }
+        }
+        Ok(drs_file)
+    }
 
-        for table in &mut drs_file.tables {
-            table.populate_index_map();
+    /// Moves the kept tables' offsets, and the header's file offset, to where they'd be if
+    /// the skipped tables had never been in the archive, so that the layout stays consistent
+    /// with the table count. File offsets are left alone, since the data hasn't moved.
+    fn lay_out_without_skipped_tables(&mut self, entry_len: u64) -> Result<()> {
+        let mut table_offset = self.header.serialized_len() + TABLE_HEADER_LEN * self.tables.len() as u64;
+        for table in &mut self.tables {
+            table.header.table_offset = to_u32(table_offset)?;
+            table_offset += entry_len * table.entries.len() as u64;
         }
+        let data_start = self.tables
+            .iter()
+            .flat_map(|t| t.entries.iter())
+            .filter(|e| e.file_size > 0)
+            .map(|e| e.file_offset)
+            .min();
+        self.header.file_offset = to_u32(data_start.unwrap_or(table_offset))?;
+        Ok(())
+    }
 
-        Ok(drs_file)
+    /// Fills in the contents of every entry from the archive, along with the build tag, and
+    /// prepares the tables for lookups. The header and tables must already be populated.
+    pub(crate) fn read_contents_from<R: Read + Seek>(&mut self, file: &mut R) -> Result<()> {
+        DrsFile::read_file_contents(file, self)?;
+        self.finish_contents(file)
     }
 
-    fn read_table_headers<R: Read>(file: &mut R, drs_file: &mut DrsFile) -> Result<()> {
-        for table_index in 0..drs_file.header.table_count {
-            drs_file.tables.push(DrsLogicalTable::new());
-            drs_file.tables[table_index as usize].header = try!(DrsTableHeader::read_from_file(file));
+    /// Reads the build tag and prepares the tables for lookups, once the contents are in.
+    pub(crate) fn finish_contents<R: Read + Seek>(&mut self, file: &mut R) -> Result<()> {
+        self.build_tag = read_build_tag(file, self)?;
+        for table in &mut self.tables {
+            table.populate_index_map();
         }
         Ok(())
     }
 
-    fn read_file_entry_headers<R: Read>(file: &mut R, drs_file: &mut DrsFile) -> Result<()> {
-        for table_index in 0..drs_file.header.table_count {
-            for _file_index in 0..drs_file.tables[table_index as usize].header.file_count {
-                let table_entry = try!(DrsTableEntry::read_from_file(file));
-                drs_file.tables[table_index as usize].entries.push(table_entry);
+    /// Reads every table header, along with whether each table should be kept (false only
+    /// when the unknown type handler asked for it to be skipped).
+    pub(crate) fn read_table_headers<R: Read + Seek>(file: &mut R,
+                                                     table_count: u32,
+                                                     mut unknown_type_handler: Option<&mut UnknownTypeHandler>)
+                                                     -> Result<(Vec<DrsTableHeader>, Vec<bool>)> {
+        // A corrupt table count would otherwise send the rest of the parse through garbage.
+        let tables_end = file.stream_position()? + TABLE_HEADER_LEN * table_count as u64;
+        let file_len = stream_len(file)?;
+        if tables_end > file_len {
+            let reason = format!("{} table headers don't fit in a {} byte archive", table_count, file_len);
+            return Err(ErrorKind::ImplausibleHeader(reason).into());
+        }
+
+        // The count is known to fit in the archive by now, so it's safe to reserve for.
+        let mut table_headers = Vec::with_capacity(table_count as usize);
+        let mut keep = Vec::with_capacity(table_count as usize);
+        for _table_index in 0..table_count {
+            let (table_header, keep_table) = DrsTableHeader::read_from_file(file, unknown_type_handler.as_deref_mut())?;
+            table_headers.push(table_header);
+            keep.push(keep_table);
+        }
+        Ok((table_headers, keep))
+    }
+
+    pub(crate) fn read_file_entry_headers<R: Read + Seek>(file: &mut R,
+                                                          table_headers: &[DrsTableHeader],
+                                                          offset_width: OffsetWidth)
+                                                          -> Result<Vec<Vec<DrsTableEntry>>> {
+        let entry_len = offset_width.entry_len();
+        let file_len = stream_len(file)?;
+        let mut entries_end = file.stream_position()?;
+        for (table_index, table_header) in table_headers.iter().enumerate() {
+            let table_end = table_header.table_offset as u64 + entry_len * table_header.file_count as u64;
+            entries_end += entry_len * table_header.file_count as u64;
+            if table_end > file_len || entries_end > file_len {
+                let reason = format!("the {} entries of table {} don't fit in a {} byte archive",
+                                     table_header.file_count,
+                                     table_index,
+                                     file_len);
+                return Err(ErrorKind::ImplausibleHeader(reason).into());
+            }
+        }
+
+        let mut table_entries = Vec::with_capacity(table_headers.len());
+        for table_header in table_headers {
+            let mut entries = Vec::with_capacity(table_header.file_count as usize);
+            for _file_index in 0..table_header.file_count {
+                entries.push(DrsTableEntry::read_from_file(file, offset_width)?);
             }
+            table_entries.push(entries);
+        }
+        Ok(table_entries)
+    }
+
+    /// Checks, before any contents are read, that every entry's data lies within an
+    /// archive of the given length. A partially downloaded archive fails here with
+    /// `TruncatedFile` naming the first file that's cut off, rather than partway through
+    /// reading the files ahead of it.
+    pub(crate) fn check_contents_fit(&self, file_len: u64) -> Result<()> {
+        for entry in self.tables.iter().flat_map(|t| t.entries.iter()) {
+            entry.check_fits(file_len)?;
         }
         Ok(())
     }
 
-    fn read_file_contents<R: Read>(file: &mut R, drs_file: &mut DrsFile) -> Result<()> {
-        for table_index in 0..drs_file.header.table_count {
-            let file_sizes: Vec<u32> = drs_file.tables[table_index as usize]
-                .entries
-                .iter()
-                .map(|e| e.file_size)
-                .collect();
-            for file_size in file_sizes {
-                let mut buffer = vec![0u8; file_size as usize];
-                try!(file.read_exact(&mut buffer[..]));
-                drs_file.tables[table_index as usize].contents.push(buffer);
+    fn read_file_contents<R: Read + Seek>(file: &mut R, drs_file: &mut DrsFile) -> Result<()> {
+        let file_len = file.seek(SeekFrom::End(0))?;
+        drs_file.check_contents_fit(file_len)?;
+        for table in &mut drs_file.tables {
+            table.contents.reserve(table.entries.len());
+            for entry in &table.entries {
+                file.seek(SeekFrom::Start(entry.file_offset))?;
+                let mut buffer = vec![0u8; entry.file_size as usize];
+                read_exact_at(file, &mut buffer[..], "file contents")?;
+                table.contents.push(buffer);
             }
         }
         Ok(())
     }
 }
 
-fn validate_str(file_name: &Path, bytes: &[u8], expected: &'static str) -> Result<()> {
-    if bytes.len() < expected.len() || &bytes[0..expected.len()] != expected.as_bytes() {
+/// Scans a buffer for every plausible DRS header, returning their byte offsets in ascending
+/// order. A header is plausible when one of the known copyright strings is followed by the
+/// matching version and type strings, with room left for the table count and file offset.
+/// An intact archive yields just `[0]`; additional offsets usually mean that several
+/// archives were glued together by a misbehaving build tool.
+pub fn find_embedded_headers(bytes: &[u8]) -> Vec<usize> {
+    let signatures = [(EXPECTED_AOE_COPYRIGHT, AOE_COPYRIGHT_LEN, EXPECTED_AOE_VERSION, EXPECTED_AOE_TYPE),
+                      (EXPECTED_SWBG_COPYRIGHT, SWBG_COPYRIGHT_LEN, EXPECTED_SWBG_VERSION, EXPECTED_SWBG_TYPE)];
+    let mut offsets = Vec::new();
+    for start in 0..bytes.len() {
+        let candidate = &bytes[start..];
+        for &(copyright, copyright_len, version, file_type) in &signatures {
+            if candidate.len() >= copyright_len + 24 && candidate.starts_with(copyright.as_bytes()) &&
+               candidate[copyright_len..].starts_with(version.as_bytes()) &&
+               candidate[copyright_len + 4..].starts_with(file_type.as_bytes()) {
+                offsets.push(start);
+            }
+        }
+    }
+    offsets
+}
+
+/// Where the metadata of an archive ends and its file data can begin: past a header of
+/// `header_len` bytes, a table header for each of the tables, and then every table's
+/// entries, given as the number of entries in each table and the size of one entry.
+pub(crate) fn metadata_end<I: IntoIterator<Item = u64>>(header_len: u64, entry_counts: I, entry_len: u64) -> u64 {
+    let (table_count, entry_count) = entry_counts.into_iter()
+        .fold((0, 0), |(tables, entries), count| (tables + 1, entries + count));
+    header_len + TABLE_HEADER_LEN * table_count + entry_len * entry_count
+}
+
+/// Some tools write a zero (or otherwise bogus) `file_offset` into the header and leave it
+/// to readers to work out where file data starts. Contents are read from each entry's own
+/// offset, so it isn't needed for that, but it's replaced with the end of the entry headers
+/// whenever it points into the metadata or past the end of the archive so that it can be
+/// relied on afterwards.
+pub(crate) fn repair_file_offset<R: Seek>(file: &mut R,
+                                          header: &mut DrsHeader,
+                                          table_headers: &[DrsTableHeader],
+                                          offset_width: OffsetWidth)
+                                          -> Result<()> {
+    let metadata_end = metadata_end(header.serialized_len(),
+                                    table_headers.iter().map(|t| t.file_count as u64),
+                                    offset_width.entry_len());
+
+    let file_len = stream_len(file)?;
+
+    let file_offset = header.file_offset as u64;
+    if file_offset < metadata_end || file_offset > file_len {
+        header.file_offset = to_u32(metadata_end)?;
+    }
+    Ok(())
+}
+
+/// Marks the end of the optional trailer written after the file data, which is laid out as
+/// the UTF-8 build tag, its length as a u32, and then these bytes. The game ignores
+/// anything past the last file, so the trailer doesn't affect loading.
+pub(crate) const BUILD_TAG_MAGIC: &[u8; 8] = b"DRSBUILD";
+
+/// Looks for a build tag trailer at the end of the stream. It's only recognized if it lies
+/// entirely past the metadata and the data of every file, so file contents that happen to
+/// end in the magic bytes aren't mistaken for one.
+fn read_build_tag<R: Read + Seek>(file: &mut R, drs_file: &DrsFile) -> Result<Option<String>> {
+    let file_len = stream_len(file)?;
+    let data_end = data_end(drs_file);
+    let footer_len = 4 + BUILD_TAG_MAGIC.len() as u64;
+    if file_len < data_end + footer_len {
+        return Ok(None);
+    }
+
+    file.seek(SeekFrom::Start(file_len - footer_len))?;
+    let tag_len = read_u32_at(file, "build tag length")? as u64;
+    let mut magic = [0u8; 8];
+    read_exact_at(file, &mut magic, "build tag magic")?;
+    if &magic != BUILD_TAG_MAGIC || tag_len > file_len - footer_len - data_end {
+        return Ok(None);
+    }
+
+    let mut tag = vec![0u8; tag_len as usize];
+    file.seek(SeekFrom::Start(file_len - footer_len - tag_len))?;
+    read_exact_at(file, &mut tag, "build tag")?;
+    Ok(String::from_utf8(tag).ok())
+}
+
+/// Where the metadata and the data of every file end; a build tag can only start here or later.
+pub(crate) fn data_end(drs_file: &DrsFile) -> u64 {
+    drs_file.tables
+        .iter()
+        .flat_map(|t| t.entries.iter())
+        .filter(|e| e.file_size > 0)
+        .map(|e| e.file_offset + e.file_size)
+        .fold(drs_file.header.file_offset as u64, u64::max)
+}
+
+/// The total length of the stream, leaving the current position untouched.
+pub(crate) fn stream_len<R: Seek>(file: &mut R) -> Result<u64> {
+    let position = file.stream_position()?;
+    let len = file.seek(SeekFrom::End(0))?;
+    file.seek(SeekFrom::Start(position))?;
+    Ok(len)
+}
+
+/// Copies a string into a fixed-size, null-padded field.
+fn copy_str(dest: &mut [u8], value: &str) {
+    dest[..value.len()].copy_from_slice(value.as_bytes());
+}
+
+/// Reads a u32, reporting where in the stream the read was attempted if it fails.
+pub(crate) fn read_u32_at<R: Read + Seek>(file: &mut R, what: &'static str) -> Result<u32> {
+    let at = file.stream_position()?;
+    file.read_u32().chain_err(|| ErrorKind::ReadFailed { at, what })
+}
+
+/// Reads a u64, reporting where in the stream the read was attempted if it fails.
+fn read_u64_at<R: Read + Seek>(file: &mut R, what: &'static str) -> Result<u64> {
+    let at = file.stream_position()?;
+    byteorder::ReadBytesExt::read_u64::<LittleEndian>(file).chain_err(|| ErrorKind::ReadFailed { at, what })
+}
+
+/// Fills the buffer, reporting where in the stream the read was attempted if it fails.
+fn read_exact_at<R: Read + Seek>(file: &mut R, buf: &mut [u8], what: &'static str) -> Result<()> {
+    let at = file.stream_position()?;
+    file.read_exact(buf).chain_err(|| ErrorKind::ReadFailed { at, what })
+}
+
+/// DRS offsets and sizes are 32 bits wide, which caps the size of an archive.
+pub(crate) fn to_u32(value: u64) -> Result<u32> {
+    if value > u32::MAX as u64 {
+        return Err(ErrorKind::ExceedsFormatLimit(value).into());
+    }
+    Ok(value as u32)
+}
+
+/// Length of the prefix `detect_header_layout` looks at: up to the end of the SWBG file type.
+const LAYOUT_PROBE_LEN: u64 = (SWBG_COPYRIGHT_LEN + 4 + 12) as u64;
+
+/// The length of the larger (SWBG) header: the probed strings plus the table count and file offset.
+#[cfg(feature = "tokio")]
+pub(crate) const MAX_HEADER_LEN: u64 = LAYOUT_PROBE_LEN + 4 + 4;
+
+/// Works out which game's header layout the stream starts with by checking each candidate
+/// in full: the SWBG copyright in bytes 0..60 with "swbg" in its file type at 64..76, or
+/// failing that the AOE copyright in bytes 0..40 with "tribe" in its file type at 44..56.
+/// Fails with `InvalidDrs` if neither layout validates, rather than guessing. Reads at most
+/// the first `LAYOUT_PROBE_LEN` bytes, from the start of the stream, and leaves the
+/// reader positioned after whatever it read.
+pub(crate) fn detect_header_layout<R: Read + Seek>(reader: &mut R,
+                                                   file_name: &Path,
+                                                   normalize: bool)
+                                                   -> Result<DrsGameType> {
+    reader.seek(SeekFrom::Start(0))?;
+    let mut prefix = Vec::new();
+    reader.by_ref().take(LAYOUT_PROBE_LEN).read_to_end(&mut prefix)?;
+
+    let candidates = [(DrsGameType::SWBG, SWBG_COPYRIGHT_LEN, EXPECTED_SWBG_COPYRIGHT, EXPECTED_SWBG_TYPE),
+                      (DrsGameType::AOE, AOE_COPYRIGHT_LEN, EXPECTED_AOE_COPYRIGHT, EXPECTED_AOE_TYPE)];
+    for &(game_type, copyright_len, expected_copyright, expected_type) in &candidates {
+        let type_start = copyright_len + 4;
+        if prefix.len() < type_start + 12 {
+            continue;
+        }
+        let copyright = &prefix[..copyright_len];
+        let file_type = &prefix[type_start..type_start + 12];
+        if validate_str(file_name, copyright, expected_copyright, normalize).is_ok() &&
+           validate_str(file_name, file_type, expected_type, normalize).is_ok() {
+            return Ok(game_type);
+        }
+    }
+    Err(ErrorKind::InvalidDrs(file_name.into()).into())
+}
+
+/// Picks a header layout without validating anything, for reading archives whose header
+/// strings are known to be nonstandard: SWBG if its file type field starts with "swbg",
+/// otherwise AOE.
+fn guess_header_layout<R: Read + Seek>(reader: &mut R) -> Result<DrsGameType> {
+    let type_start = (SWBG_COPYRIGHT_LEN + 4) as u64;
+    let mut probe = [0u8; 4];
+    reader.seek(SeekFrom::Start(type_start))?;
+    if reader.read_exact(&mut probe).is_ok() && matches_str(&probe, EXPECTED_SWBG_TYPE.as_bytes()) {
+        Ok(DrsGameType::SWBG)
+    } else {
+        Ok(DrsGameType::AOE)
+    }
+}
+
+fn validate_str(file_name: &Path, bytes: &[u8], expected: &'static str, normalize: bool) -> Result<()> {
+    let matches = if normalize {
+        matches_str(trim_terminators(bytes), trim_terminators(expected.as_bytes()))
+    } else {
+        matches_str(bytes, expected.as_bytes())
+    };
+    if !matches {
         return Err(ErrorKind::InvalidDrs(file_name.into()).into());
     }
     Ok(())
 }
+
+/// A header string field up to its first null byte, with its terminators trimmed and any
+/// invalid UTF-8 replaced. Every way of reading a header string as text goes through this.
+fn decode_field(bytes: &[u8]) -> Cow<'_, str> {
+    let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(trim_terminators(&bytes[..end]))
+}
+
+/// Strips the trailing null, SUB (0x1A) or other control, and space bytes that different
+/// tools use to terminate and pad header strings.
+fn trim_terminators(bytes: &[u8]) -> &[u8] {
+    let end = bytes.iter().rposition(|&b| b > b' ' && b != 0x7F).map_or(0, |i| i + 1);
+    &bytes[..end]
+}
+
+/// True if the field starts with the expected string.
+fn matches_str(bytes: &[u8], expected: &[u8]) -> bool {
+    bytes.starts_with(expected)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{make_empty_file_archive, make_hand_built_archive, make_test_archive, make_truncated_archive,
+                   make_zeroed_file_offset_archive};
+    use crate::fixtures::write_temp_archive;
+    use crate::writer::DrsWriteOptions;
+
+    /// An AOE archive with one Wav table whose entries use 64-bit offsets and sizes.
+    fn wide_archive(entries: &[(u32, u64, u64)]) -> Vec<u8> {
+        let mut header = DrsHeader::new(DrsGameType::AOE);
+        header.table_count = 1;
+        header.file_offset = (header.serialized_len() + 12 + 20 * entries.len() as u64) as u32;
+        let mut bytes = Vec::new();
+        header.write_to(&mut bytes).unwrap();
+        let table_header = DrsTableHeader {
+            file_type: DrsFileType::Wav,
+            table_offset: (header.serialized_len() + 12) as u32,
+            file_count: entries.len() as u32,
+        };
+        table_header.write_to(&mut bytes).unwrap();
+        for &(file_id, file_offset, file_size) in entries {
+            bytes.write_u32(file_id).unwrap();
+            bytes.extend_from_slice(&file_offset.to_le_bytes());
+            bytes.extend_from_slice(&file_size.to_le_bytes());
+        }
+        bytes
+    }
+
+    fn range_ids(table: &DrsLogicalTable, range: Range<u32>) -> Vec<u32> {
+        table.files_in_range(range).map(|(entry, _)| entry.file_id).collect()
+    }
+
+    #[test]
+    fn files_in_range_follows_pushes_and_removals() {
+        let mut table = DrsLogicalTable::new();
+        for &file_id in &[1, 3, 5] {
+            table.push_file(file_id, vec![file_id as u8]).unwrap();
+        }
+        assert!(table.sorted_by_id);
+        assert_eq!(range_ids(&table, 2..6), [3, 5]);
+
+        table.push_file(2, vec![2]).unwrap();
+        assert!(!table.sorted_by_id);
+        assert_eq!(range_ids(&table, 2..6), [3, 5, 2]);
+
+        table.remove_file(2).unwrap();
+        assert!(table.sorted_by_id);
+        assert_eq!(range_ids(&table, 0..4), [1, 3]);
+    }
+
+    #[test]
+    fn files_in_range_of_unsorted_parts() {
+        let entries: Vec<DrsTableEntry> = [9, 4, 6]
+            .iter()
+            .map(|&file_id| DrsTableEntry { file_id, file_offset: 0, file_size: 0 })
+            .collect();
+        let table = DrsLogicalTable::from_parts(DrsTableHeader::new(), entries, vec![Vec::new(); 3]).unwrap();
+        assert!(!table.sorted_by_id);
+        assert_eq!(range_ids(&table, 5..10), [9, 6]);
+    }
+
+    #[test]
+    fn empty_files_read_back_empty() {
+        let bytes = make_empty_file_archive(DrsGameType::AOE);
+        let drs_file = DrsFile::from_bytes(&bytes).unwrap();
+        assert_eq!(drs_file.find_file(50501).unwrap(), b"");
+        assert_eq!(drs_file.find_file(50502).unwrap(), b"terrain");
+        assert_eq!(drs_file.find_file(2).unwrap(), b"");
+        let (_, entry) = drs_file.find_entry(2).unwrap();
+        assert_eq!(entry.file_offset, bytes.len() as u64);
+    }
+
+    #[test]
+    fn metadata_ends_where_hand_built_data_starts() {
+        for &game in &[DrsGameType::AOE, DrsGameType::SWBG] {
+            let drs_file = DrsFile::from_bytes(&make_hand_built_archive(game)).unwrap();
+            assert_eq!(metadata_end(game.header_len(), vec![1, 1], TABLE_ENTRY_LEN),
+                       drs_file.header.file_offset as u64);
+        }
+        assert_eq!(metadata_end(64, vec![], OffsetWidth::Wide.entry_len()), 64);
+        assert_eq!(metadata_end(64, vec![2, 3], OffsetWidth::Wide.entry_len()), 64 + 2 * 12 + 5 * 20);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn metadata_round_trips_through_json() {
+        let bytes = make_test_archive(DrsGameType::SWBG,
+                                      &[(DrsFileType::Binary, 50500, b"palette"),
+                                        (DrsFileType::Slp, 1, b"one"),
+                                        (DrsFileType::Unknown(0x61626364), 2, b"unknown")]);
+        let options = DrsReadOptions {
+            unknown_type_handler: Some(Box::new(|_| UnknownTypeAction::AsOther)),
+            ..DrsReadOptions::default()
+        };
+        let path = write_temp_archive("serde.drs", &bytes);
+        let drs_file = DrsFile::read_from_file_with_options(&path, options).unwrap();
+
+        let metadata = drs_file.header.metadata();
+        let json = serde_json::to_string(&metadata).unwrap();
+        assert_eq!(serde_json::from_str::<DrsHeaderMetadata>(&json).unwrap(), metadata);
+
+        let tables: Vec<(&DrsTableHeader, &Vec<DrsTableEntry>)> =
+            drs_file.tables.iter().map(|table| (&table.header, &table.entries)).collect();
+        let json = serde_json::to_string(&tables).unwrap();
+        let read_back: Vec<(DrsTableHeader, Vec<DrsTableEntry>)> = serde_json::from_str(&json).unwrap();
+        assert_eq!(read_back.len(), drs_file.tables.len());
+        for ((header, entries), table) in read_back.iter().zip(&drs_file.tables) {
+            assert_eq!(header, &table.header);
+            assert_eq!(entries, &table.entries);
+        }
+    }
+
+    #[test]
+    fn file_type_tags_round_trip() {
+        for &file_type in &[DrsFileType::Binary, DrsFileType::Slp, DrsFileType::Shp, DrsFileType::Wav] {
+            assert_eq!(DrsFileType::try_from(file_type.to_u32()).unwrap(), file_type);
+            assert_eq!(DrsFileType::try_from(u32::from(file_type)).unwrap(), file_type);
+        }
+        assert_eq!(DrsFileType::Binary.to_u32(), 0x62696E61);
+        assert_eq!(DrsFileType::Wav.to_u32(), 0x77617620);
+
+        // Unknown tags come back out unchanged, and converting them fails with the same tag.
+        let unknown = DrsFileType::Unknown(0x61626364);
+        assert_eq!(unknown.to_u32(), 0x61626364);
+        match DrsFileType::try_from(unknown.to_u32()) {
+            Err(Error(ErrorKind::UnknownFileType(0x61626364), _)) => {},
+            other => panic!("expected UnknownFileType, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn input_shorter_than_a_header_is_too_small() {
+        let header = &make_hand_built_archive(DrsGameType::AOE)[..64];
+        for &len in &[0, 63] {
+            match DrsHeader::read_from(&mut Cursor::new(&header[..len]), Path::new("short.drs")) {
+                Err(err @ Error(ErrorKind::TooSmall(..), _)) => {
+                    assert!(err.to_string().contains(&format!("only {} bytes long", len)), "{}", err)
+                },
+                other => panic!("expected TooSmall, got {:?}", other),
+            }
+        }
+
+        // At 64 bytes the header is complete, so it's validated instead.
+        assert_eq!(DrsHeader::read_from(&mut Cursor::new(header), Path::new("header.drs")).unwrap().game_type(),
+                   DrsGameType::AOE);
+        match DrsHeader::read_from(&mut Cursor::new(&[0xFFu8; 64][..]), Path::new("garbage.drs")) {
+            Err(Error(ErrorKind::InvalidDrs(_), _)) => {},
+            other => panic!("expected InvalidDrs, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn header_write_and_read_are_inverses() {
+        for &game in &[DrsGameType::AOE, DrsGameType::SWBG] {
+            let on_disk = make_hand_built_archive(game)[..game.header_len() as usize].to_vec();
+            let header = DrsHeader::read_from(&mut Cursor::new(&on_disk), Path::new("header")).unwrap();
+            assert_eq!(header.game_type(), game);
+            assert_eq!((header.table_count, header.file_offset), (2, game.header_len() as u32 + 48));
+
+            let mut written = Vec::new();
+            header.write_to(&mut written).unwrap();
+            assert_eq!(written.len() as u64, game.header_len());
+            assert_eq!(written, on_disk);
+            assert_eq!(DrsHeader::read_from(&mut Cursor::new(&written), Path::new("header")).unwrap(), header);
+        }
+    }
+
+    #[test]
+    fn remove_file_drops_emptied_tables() {
+        let bytes = make_test_archive(DrsGameType::AOE,
+                                      &[(DrsFileType::Slp, 1, b"one"),
+                                        (DrsFileType::Slp, 2, b"two"),
+                                        (DrsFileType::Wav, 7, b"RIFF")]);
+        let mut drs_file = DrsFile::from_bytes(&bytes).unwrap();
+
+        assert_eq!(drs_file.remove_file(1).unwrap(), b"one");
+        assert_eq!(drs_file.find_file(1), None);
+        assert_eq!(drs_file.find_table(DrsFileType::Slp).unwrap().header.file_count, 1);
+        assert_eq!(drs_file.header.table_count, 2);
+
+        assert_eq!(drs_file.remove_file(7).unwrap(), b"RIFF");
+        assert!(drs_file.find_table(DrsFileType::Wav).is_none());
+        assert_eq!(drs_file.header.table_count, 1);
+        drs_file.validate_counts().unwrap();
+
+        assert_eq!(drs_file.remove_file(7), None);
+        assert_eq!(drs_file.find_file(2).unwrap(), b"two");
+    }
+
+    fn read_with_unknown_type_action(name: &str, action: UnknownTypeAction) -> DrsFile {
+        // The unknown table sits between the others, with its data between theirs.
+        let files: [(DrsFileType, u32, &[u8]); 3] = [(DrsFileType::Slp, 1, b"one"),
+                                                     (DrsFileType::Unknown(0x61626364), 2, b"unknown"),
+                                                     (DrsFileType::Wav, 7, b"RIFF")];
+        let path = write_temp_archive(name, &make_test_archive(DrsGameType::AOE, &files));
+        let options = DrsReadOptions {
+            unknown_type_handler: Some(Box::new(move |_| action)),
+            ..DrsReadOptions::default()
+        };
+        DrsFile::read_from_file_with_options(&path, options).unwrap()
+    }
+
+    #[test]
+    fn skipped_unknown_table_leaves_a_consistent_archive() {
+        let drs_file = read_with_unknown_type_action("skip_unknown.drs", UnknownTypeAction::Skip);
+        drs_file.validate_integrity().unwrap();
+        assert_eq!(drs_file.header.table_count, 2);
+        assert_eq!(drs_file.tables.iter().map(|t| t.header.file_type).collect::<Vec<_>>(),
+                   [DrsFileType::Slp, DrsFileType::Wav]);
+        assert_eq!(drs_file.find_file(1).unwrap(), b"one");
+        assert_eq!(drs_file.find_file(2), None);
+        assert_eq!(drs_file.find_file(7).unwrap(), b"RIFF");
+    }
+
+    #[test]
+    fn unknown_table_read_as_binary() {
+        let drs_file = read_with_unknown_type_action("binary_unknown.drs", UnknownTypeAction::AsBinary);
+        drs_file.validate_integrity().unwrap();
+        assert_eq!(drs_file.header.table_count, 3);
+        assert_eq!(drs_file.tables.iter().map(|t| t.header.file_type).collect::<Vec<_>>(),
+                   [DrsFileType::Slp, DrsFileType::Binary, DrsFileType::Wav]);
+        assert_eq!(drs_file.find_file_with_type(2).unwrap(), (DrsFileType::Binary, &b"unknown".to_vec()));
+    }
+
+    #[test]
+    fn extensions_of_unknown_types() {
+        let unknown = DrsFileType::Unknown(0x64636261);
+        assert_eq!(unknown.extension(), "bin");
+        assert_eq!(unknown.tagged_extension(), "x64636261");
+        assert_eq!(DrsFileType::Slp.tagged_extension(), DrsFileType::Slp.extension());
+        assert_eq!(DrsFileType::from_extension(&unknown.tagged_extension()), Some(unknown));
+        assert_eq!(DrsFileType::from_extension("X736C7020"), Some(DrsFileType::Slp));
+    }
+
+    #[test]
+    fn header_strings_decode_the_same_everywhere() {
+        let mut header = DrsHeader::new(DrsGameType::AOE);
+        let mut copyright = [0u8; 40];
+        copyright[..12].copy_from_slice(b"Copyright \x1a ");
+        copyright[13..17].copy_from_slice(b"junk");
+        header.copyright_info = DrsCopyright::Aoe(copyright);
+        header.file_version = *b"1.00";
+        header.file_type[..6].copy_from_slice(b"tribe ");
+
+        let metadata = header.metadata();
+        assert_eq!(header.copyright_str(), "Copyright");
+        assert_eq!(header.copyright_info.decoded(), header.copyright_str());
+        assert_eq!(metadata.copyright, header.copyright_str());
+        assert_eq!(metadata.file_version, header.version_str());
+        assert_eq!(metadata.file_type, header.file_type_str());
+        assert_eq!(header.file_type_str(), "tribe");
+    }
+
+    #[test]
+    fn version_needs_two_minor_digits() {
+        let mut header = DrsHeader::new(DrsGameType::AOE);
+        for &(file_version, version) in &[(b"1.00", Some((1, 0))),
+                                          (b"1.05", Some((1, 5))),
+                                          (b"1.50", Some((1, 50))),
+                                          (b"1.5\0", None),
+                                          (b"1.x5", None)] {
+            header.file_version = *file_version;
+            assert_eq!(header.version(), version, "{:?}", header.version_str());
+        }
+    }
+
+    #[test]
+    fn overflowing_total_size_is_too_large() {
+        let huge = u64::MAX / 2 + 1;
+        let bytes = wide_archive(&[(1, 0, huge), (2, 0, huge)]);
+        let options = DrsReadOptions {
+            offset_width: OffsetWidth::Wide,
+            max_total_bytes: Some(1024),
+            ..DrsReadOptions::default()
+        };
+        match DrsFile::read_from_with_options(&mut Cursor::new(bytes), Path::new("wide"), options) {
+            Err(Error(ErrorKind::ArchiveTooLarge(total, 1024), _)) => assert_eq!(total, u64::MAX),
+            other => panic!("expected ArchiveTooLarge, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn zeroed_file_offset_is_repaired() {
+        let intact = DrsFile::from_bytes(&make_test_archive(DrsGameType::AOE, &[(DrsFileType::Slp, 1, b"one")]))
+            .unwrap();
+        let repaired = DrsFile::from_bytes(&make_zeroed_file_offset_archive(DrsGameType::AOE)).unwrap();
+        assert_eq!(repaired.header.file_offset, intact.header.file_offset);
+        assert_eq!(repaired.find_file(1).unwrap(), b"one");
+    }
+
+    #[test]
+    fn overrunning_entry_is_truncated_file() {
+        match DrsFile::from_bytes(&make_truncated_archive(DrsGameType::AOE)) {
+            Err(Error(ErrorKind::TruncatedFile { file_id: 7, expected: 8, available: 5 }, _)) => {},
+            other => panic!("expected TruncatedFile, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn empty_file_at_offset_zero_passes_integrity() {
+        let mut drs_file = DrsFile::from_bytes(&make_empty_file_archive(DrsGameType::AOE)).unwrap();
+        drs_file.validate_integrity().unwrap();
+        for entry in drs_file.tables.iter_mut().flat_map(|t| t.entries.iter_mut()) {
+            if entry.file_size == 0 {
+                entry.file_offset = 0;
+            }
+        }
+        drs_file.validate_integrity().unwrap();
+    }
+
+    #[test]
+    fn archives_equal_after_rewriting() {
+        let bytes = make_test_archive(DrsGameType::AOE,
+                                      &[(DrsFileType::Wav, 9, b"nine"),
+                                        (DrsFileType::Slp, 2, b"two"),
+                                        (DrsFileType::Wav, 3, b"three")]);
+        let original = DrsFile::from_bytes(&bytes).unwrap();
+        let mut rewritten = Vec::new();
+        original.write_to_with_options(&mut rewritten, &DrsWriteOptions::default()).unwrap();
+        let rewritten = DrsFile::from_bytes(&rewritten).unwrap();
+        assert!(original.diff(&rewritten).is_empty());
+        assert_eq!(original, rewritten);
+
+        let mut changed = DrsFile::from_bytes(&bytes).unwrap();
+        changed.replace_file(3, b"THREE".to_vec()).unwrap();
+        assert!(original != changed);
+
+        // The raw metadata describes the layout, so it does differ.
+        let (original_wavs, rewritten_wavs) = (original.find_table(DrsFileType::Wav).unwrap(),
+                                               rewritten.find_table(DrsFileType::Wav).unwrap());
+        assert!(original_wavs.header != rewritten_wavs.header);
+        assert!(original_wavs.entries != rewritten_wavs.entries);
+        assert_eq!(original_wavs, rewritten_wavs);
+    }
+}
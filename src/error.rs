@@ -20,6 +20,8 @@
 // SOFTWARE.
 //
 
+use crate::drs::{DrsFileType, DrsGameType};
+
 use std::io;
 use std::path::PathBuf;
 use std::string::FromUtf8Error;
@@ -42,5 +44,98 @@ error_chain! {
             description("invalid DRS")
             display("Invalid DRS: {:?}", path)
         }
+        TooSmall(path: PathBuf, file_len: u64) {
+            description("file too small to be a DRS archive")
+            display("{:?} is too small to be a DRS archive: it's only {} bytes long", path, file_len)
+        }
+        InconsistentHeader(game_type: DrsGameType, field: &'static str) {
+            description("header fields disagree on the game")
+            display("Header has the {:?} copyright, but its {} doesn't match", game_type, field)
+        }
+        UnknownFileType(binary_val: u32) {
+            description("unknown file type")
+            display("Unknown table file type 0x{:08X}", binary_val)
+        }
+        ImplausibleHeader(reason: String) {
+            description("implausible archive header")
+            display("Implausible archive header: {}", reason)
+        }
+        ReadFailed { at: u64, what: &'static str } {
+            description("read failed")
+            display("Failed to read {} at byte offset {}", what, at)
+        }
+        DigestMissing(file_type: DrsFileType, file_id: u32) {
+            description("no expected digest for file")
+            display("No expected digest was provided for {:?} file {}", file_type, file_id)
+        }
+        DigestMismatch(file_type: DrsFileType, file_id: u32) {
+            description("file digest mismatch")
+            display("Digest mismatch for {:?} file {}", file_type, file_id)
+        }
+        ArchiveTooLarge(total: u64, limit: u64) {
+            description("archive too large")
+            display("Archive contents total {} bytes, which exceeds the limit of {} bytes", total, limit)
+        }
+        FileIndexOutOfRange(index: usize, count: usize) {
+            description("file index out of range")
+            display("File index {} is out of range; the archive has {} files", index, count)
+        }
+        InconsistentTable(table_index: usize, reason: String) {
+            description("inconsistent table")
+            display("Table {} is inconsistent: {}", table_index, reason)
+        }
+        InvalidCache(reason: String) {
+            description("invalid entry cache")
+            display("Invalid entry cache: {}", reason)
+        }
+        InvalidTableParts(reason: String) {
+            description("table parts don't fit together")
+            display("Can't assemble table: {}", reason)
+        }
+        DuplicateFileId { file_type: DrsFileType, file_id: u32 } {
+            description("duplicate file ID")
+            display("The {:?} table contains more than one file with ID {}", file_type, file_id)
+        }
+        TableCountMismatch(declared: u32, actual: usize) {
+            description("table count doesn't match the tables")
+            display("Header declares {} tables, but there are {}", declared, actual)
+        }
+        ExceedsFormatLimit(value: u64) {
+            description("value too large for the DRS format")
+            display("{} does not fit in the 32-bit offsets and sizes used by DRS archives", value)
+        }
+        TruncatedFile { file_id: u32, expected: u64, available: u64 } {
+            description("file extends past the end of the archive")
+            display("File {} is {} bytes long, but only {} bytes remain in the archive",
+                    file_id, expected, available)
+        }
+        ShortRead { offset: u64, size: u64 } {
+            description("archive ended in the middle of a read")
+            display("The archive ended before {} bytes could be read at byte offset {}", size, offset)
+        }
+        InvalidCopyrightLength(expected: usize, actual: usize) {
+            description("invalid copyright length")
+            display("Copyright must be {} bytes long, but {} bytes were given", expected, actual)
+        }
+        FileNotFound(file_type: DrsFileType, file_id: u32) {
+            description("file not found")
+            display("The archive has no {:?} file with ID {}", file_type, file_id)
+        }
+        FileIdNotFound(file_id: u32) {
+            description("file ID not found")
+            display("The archive has no file with ID {}", file_id)
+        }
+        ExtractFailed(file_type: DrsFileType, file_id: u32) {
+            description("failed to extract file")
+            display("Failed to extract {:?} file {}", file_type, file_id)
+        }
+        InvalidFileName(name: String) {
+            description("invalid file name")
+            display("Invalid file name (expected \"<file id>.<extension>\"): {:?}", name)
+        }
+        BrokenReference(file_type: DrsFileType, file_id: u32, binary_id: u32) {
+            description("reference to a missing binary file")
+            display("{:?} file {} refers to binary file {}, which isn't in the archive", file_type, file_id, binary_id)
+        }
     }
 }
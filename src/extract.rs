@@ -0,0 +1,204 @@
+// Chariot: An open source reimplementation of Age of Empires (1997)
+// Copyright (c) 2016 Kevin Fuller
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+//
+
+use crate::drs::{DrsFile, DrsFileType, DrsTableEntry};
+use crate::error::*;
+
+use std::fs;
+use std::fs::File;
+use std::io::prelude::*;
+use std::path::Path;
+
+#[cfg(feature = "sha2")]
+use sha2::{Digest, Sha256};
+#[cfg(feature = "sha2")]
+use std::collections::HashMap;
+
+impl DrsFile {
+    /// Extracts a single file, named the way extracted files are named (i.e., "50500.slp"),
+    /// into the given directory. The extension selects the table to look in.
+    /// Returns false if the archive doesn't contain the requested file.
+    pub fn extract_by_name<P: AsRef<Path>>(&self, name: &str, out_dir: P) -> Result<bool> {
+        let (file_type, file_id) = parse_file_name(name)?;
+        let contents = match self.find_table(file_type).and_then(|t| t.find_file_contents(file_id)) {
+            Some(contents) => contents,
+            None => return Ok(false),
+        };
+        write_extracted_file(out_dir.as_ref(), file_type, file_id, contents)?;
+        Ok(true)
+    }
+
+    /// Writes the contents of the file with the given ID, from whichever table it's in (the
+    /// first match, as with `find_file`), to `out`. Fails with `FileIdNotFound` if the
+    /// archive has no file with that ID.
+    pub fn export_file<W: Write>(&self, file_id: u32, out: &mut W) -> Result<()> {
+        let contents = self.find_file(file_id).ok_or(ErrorKind::FileIdNotFound(file_id))?;
+        out.write_all(contents)?;
+        Ok(())
+    }
+
+    /// Extracts every file in the archive into the given directory, which is created if it
+    /// doesn't exist. Files are named "<file id>.<extension>". Returns the number of files
+    /// written; if one can't be written, the error names it.
+    pub fn extract_all<P: AsRef<Path>>(&self, out_dir: P) -> Result<usize> {
+        self.extract_all_where(out_dir, |_, _| true)
+    }
+
+    /// Like `extract_all`, but files go into a subdirectory per file type named after the
+    /// extension, such as "slp/1.slp" and "wav/7.wav".
+    pub fn extract_to_type_dirs<P: AsRef<Path>>(&self, out_dir: P) -> Result<usize> {
+        let out_dir = out_dir.as_ref();
+        let mut count = 0;
+        for table in &self.tables {
+            let type_dir = out_dir.join(&*table.header.file_type.tagged_extension());
+            for (entry, contents) in table.entries.iter().zip(&table.contents) {
+                write_extracted_file(&type_dir, table.header.file_type, entry.file_id, contents)?;
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+
+    /// Like `extract_all`, but only writes the files `predicate` returns true for. The
+    /// predicate sees files in archive order, so it can also sample, e.g. by counting how
+    /// many files of each type it has accepted so far. Returns the number of files written.
+    pub fn extract_all_where<P, F>(&self, out_dir: P, mut predicate: F) -> Result<usize>
+        where P: AsRef<Path>,
+              F: FnMut(DrsFileType, &DrsTableEntry) -> bool
+    {
+        let out_dir = out_dir.as_ref();
+        let mut count = 0;
+        for table in &self.tables {
+            for (entry, contents) in table.entries.iter().zip(&table.contents) {
+                if !predicate(table.header.file_type, entry) {
+                    continue;
+                }
+                write_extracted_file(out_dir, table.header.file_type, entry.file_id, contents)?;
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+
+    /// Like `extract_all`, but first checks the SHA-256 digest of every file against
+    /// the expected digests. Nothing is written unless every file in the archive has
+    /// an expected digest and matches it.
+    #[cfg(feature = "sha2")]
+    pub fn extract_all_verified<P: AsRef<Path>>(&self,
+                                                out_dir: P,
+                                                digests: &HashMap<(DrsFileType, u32), [u8; 32]>)
+                                                -> Result<usize> {
+        for table in &self.tables {
+            let file_type = table.header.file_type;
+            for (entry, contents) in table.entries.iter().zip(&table.contents) {
+                let expected = match digests.get(&(file_type, entry.file_id)) {
+                    Some(expected) => expected,
+                    None => return Err(ErrorKind::DigestMissing(file_type, entry.file_id).into()),
+                };
+                if Sha256::digest(contents).as_slice() != &expected[..] {
+                    return Err(ErrorKind::DigestMismatch(file_type, entry.file_id).into());
+                }
+            }
+        }
+        self.extract_all(out_dir)
+    }
+}
+
+/// Splits a file name like "50500.slp" into its file type and file ID.
+pub fn parse_file_name(name: &str) -> Result<(DrsFileType, u32)> {
+    let invalid = || -> Error { ErrorKind::InvalidFileName(name.into()).into() };
+    let dot = name.rfind('.').ok_or_else(&invalid)?;
+    let file_id = name[..dot].parse::<u32>().map_err(|_| invalid())?;
+    let file_type = DrsFileType::from_extension(&name[dot + 1..]).ok_or_else(&invalid)?;
+    Ok((file_type, file_id))
+}
+
+fn write_extracted_file(out_dir: &Path, file_type: DrsFileType, file_id: u32, contents: &[u8]) -> Result<()> {
+    let write = || -> Result<()> {
+        fs::create_dir_all(out_dir)?;
+        let mut file = File::create(out_dir.join(format!("{}.{}", file_id, file_type.tagged_extension())))?;
+        file.write_all(contents)?;
+        Ok(())
+    };
+    write().chain_err(|| ErrorKind::ExtractFailed(file_type, file_id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::drs::DrsGameType;
+    use crate::fixtures::make_test_archive;
+
+    #[test]
+    fn export_file_writes_contents() {
+        let files: [(DrsFileType, u32, &[u8]); 2] = [(DrsFileType::Slp, 1, b"one"), (DrsFileType::Wav, 7, b"RIFF")];
+        let drs_file = DrsFile::from_bytes(&make_test_archive(DrsGameType::AOE, &files)).unwrap();
+        let mut out = Vec::new();
+        drs_file.export_file(7, &mut out).unwrap();
+        assert_eq!(out, b"RIFF");
+    }
+
+    #[test]
+    fn export_file_of_missing_id_is_not_found() {
+        let drs_file = DrsFile::from_bytes(&make_test_archive(DrsGameType::AOE, &[(DrsFileType::Slp, 1, b"one")]))
+            .unwrap();
+        let mut out = Vec::new();
+        match drs_file.export_file(2, &mut out) {
+            Err(Error(ErrorKind::FileIdNotFound(2), _)) => {},
+            other => panic!("expected FileIdNotFound, got {:?}", other),
+        }
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn extract_all_creates_the_directory_and_names_files_by_id() {
+        let files: [(DrsFileType, u32, &[u8]); 2] = [(DrsFileType::Slp, 1, b"one"), (DrsFileType::Wav, 7, b"RIFF")];
+        let drs_file = DrsFile::from_bytes(&make_test_archive(DrsGameType::AOE, &files)).unwrap();
+        let out_dir = ::std::env::temp_dir().join(format!("chariot_drs_{}_extract_all", ::std::process::id()));
+        let _ = fs::remove_dir_all(&out_dir);
+
+        assert_eq!(drs_file.extract_all(out_dir.join("nested")).unwrap(), 2);
+        assert_eq!(fs::read(out_dir.join("nested/1.slp")).unwrap(), b"one");
+        assert_eq!(fs::read(out_dir.join("nested/7.wav")).unwrap(), b"RIFF");
+        fs::remove_dir_all(&out_dir).unwrap();
+    }
+
+    #[test]
+    fn extract_all_where_writes_only_matching_files() {
+        let files: [(DrsFileType, u32, &[u8]); 3] = [(DrsFileType::Slp, 1, b"one"),
+                                                     (DrsFileType::Slp, 2, b"two"),
+                                                     (DrsFileType::Wav, 7, b"RIFF")];
+        let drs_file = DrsFile::from_bytes(&make_test_archive(DrsGameType::AOE, &files)).unwrap();
+        let out_dir = ::std::env::temp_dir().join(format!("chariot_drs_{}_extract_where", ::std::process::id()));
+        let _ = fs::remove_dir_all(&out_dir);
+
+        let written = drs_file.extract_all_where(&out_dir, |file_type, entry| {
+                file_type == DrsFileType::Wav || entry.file_id == 2
+            })
+            .unwrap();
+        assert_eq!(written, 2);
+        let mut names: Vec<_> = fs::read_dir(&out_dir).unwrap().map(|e| e.unwrap().file_name()).collect();
+        names.sort();
+        assert_eq!(names, ["2.slp", "7.wav"]);
+        fs::remove_dir_all(&out_dir).unwrap();
+    }
+}
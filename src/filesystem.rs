@@ -0,0 +1,107 @@
+// Chariot: An open source reimplementation of Age of Empires (1997)
+// Copyright (c) 2016 Kevin Fuller
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+//
+
+use crate::drs::{DrsFile, DrsFileType};
+use crate::error::*;
+
+use std::cmp::Ordering;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Several DRS archives layered into one set of files, the way the game resolves them:
+/// when more than one archive contains the same (file type, file ID), the archive that
+/// was mounted first wins.
+#[derive(Default)]
+pub struct DrsFileSystem {
+    archives: Vec<(PathBuf, DrsFile)>,
+}
+
+impl DrsFileSystem {
+    pub fn new() -> DrsFileSystem {
+        Default::default()
+    }
+
+    /// Loads every `.drs` file in `dir` and mounts them in order. Archives whose file stem
+    /// matches a name in `priority` (case-insensitively, so "graphics" matches
+    /// "GRAPHICS.DRS") come first, in the order of that list; the rest follow sorted by
+    /// file name.
+    pub fn from_dir<P: AsRef<Path>>(dir: P, priority: &[&str]) -> Result<DrsFileSystem> {
+        let mut paths = Vec::new();
+        for dir_entry in fs::read_dir(dir)? {
+            let path = dir_entry?.path();
+            let is_drs = path.extension()
+                .and_then(|ext| ext.to_str())
+                .is_some_and(|ext| ext.eq_ignore_ascii_case("drs"));
+            if is_drs && path.is_file() {
+                paths.push(path);
+            }
+        }
+
+        let rank = |path: &Path| {
+            let stem = path.file_stem().and_then(|stem| stem.to_str()).unwrap_or("");
+            priority.iter().position(|name| name.eq_ignore_ascii_case(stem))
+        };
+        paths.sort_by(|a, b| match (rank(a), rank(b)) {
+            (Some(a_rank), Some(b_rank)) => a_rank.cmp(&b_rank),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => a.file_name().cmp(&b.file_name()),
+        });
+
+        let mut file_system = DrsFileSystem::new();
+        for path in paths {
+            let archive = DrsFile::read_from_file(&path)?;
+            file_system.mount(path, archive);
+        }
+        Ok(file_system)
+    }
+
+    /// Adds an archive below every archive already mounted.
+    pub fn mount<P: Into<PathBuf>>(&mut self, file_name: P, archive: DrsFile) {
+        self.archives.push((file_name.into(), archive));
+    }
+
+    /// The mounted archives and the paths they were loaded from, highest priority first.
+    pub fn archives(&self) -> &[(PathBuf, DrsFile)] {
+        &self.archives
+    }
+
+    /// Returns the contents of a file from the highest priority archive that contains it.
+    pub fn find_file_contents(&self, file_type: DrsFileType, file_id: u32) -> Option<&[u8]> {
+        self.archives
+            .iter()
+            .filter_map(|(_, archive)| archive.find_table(file_type))
+            .filter_map(|table| table.find_file_contents(file_id))
+            .map(|contents| &contents[..])
+            .next()
+    }
+
+    /// The path of the archive that `find_file_contents` would read a file from.
+    pub fn find_archive(&self, file_type: DrsFileType, file_id: u32) -> Option<&Path> {
+        self.archives
+            .iter()
+            .find(|(_, archive)| {
+                archive.find_table(file_type).and_then(|table| table.find_file_contents(file_id)).is_some()
+            })
+            .map(|(file_name, _)| file_name.as_path())
+    }
+}
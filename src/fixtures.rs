@@ -0,0 +1,235 @@
+// Chariot: An open source reimplementation of Age of Empires (1997)
+// Copyright (c) 2016 Kevin Fuller
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+//
+
+use crate::builder::DrsBuilder;
+use crate::drs::{DrsFileType, DrsGameType};
+use crate::writer::{DrsWriteOptions, EntryOrder};
+
+#[cfg(test)]
+use std::fs;
+#[cfg(test)]
+use std::ops::Deref;
+#[cfg(test)]
+use std::path::{Path, PathBuf};
+
+/// Produces the bytes of a valid archive holding the given files, for use in tests.
+/// Tables appear in the order their types first occur, entries keep the order they're
+/// given in, and file data is packed without padding, so the layout is easy to predict.
+/// This goes through the regular builder and writer so that every test shares one layout,
+/// and panics if the same file type and ID are given twice.
+pub fn make_test_archive(game: DrsGameType, files: &[(DrsFileType, u32, &[u8])]) -> Vec<u8> {
+    let mut builder = DrsBuilder::new(game);
+    for &(file_type, file_id, contents) in files {
+        builder.add_file(file_type, file_id, contents.to_vec())
+            .expect("duplicate file in test archive");
+    }
+
+    let options = DrsWriteOptions {
+        align: None,
+        entry_order: EntryOrder::AsInserted,
+        ..Default::default()
+    };
+    let mut bytes = Vec::new();
+    builder.build()
+        .expect("failed to build test archive")
+        .write_to_with_options(&mut bytes, &options)
+        .expect("failed to write test archive");
+    bytes
+}
+
+/// An archive exercising zero-length files: an empty file between two non-empty ones in
+/// the Binary table (ID 50501), and an empty file at the very end of the data in the SLP
+/// table (ID 2), which leaves its offset equal to the length of the archive.
+pub fn make_empty_file_archive(game: DrsGameType) -> Vec<u8> {
+    make_test_archive(game,
+                      &[(DrsFileType::Binary, 50500, b"palette"),
+                        (DrsFileType::Binary, 50501, b""),
+                        (DrsFileType::Binary, 50502, b"terrain"),
+                        (DrsFileType::Slp, 1, b"2.0N"),
+                        (DrsFileType::Slp, 2, b"")])
+}
+
+/// A single-file archive (SLP 1) whose header has a `file_offset` of zero, as some tools
+/// write, for checking that readers work out where the file data starts on their own.
+pub fn make_zeroed_file_offset_archive(game: DrsGameType) -> Vec<u8> {
+    let mut bytes = make_test_archive(game, &[(DrsFileType::Slp, 1, b"one")]);
+    let offset_at = game.header_len() as usize - 4;
+    bytes[offset_at..offset_at + 4].copy_from_slice(&[0; 4]);
+    bytes
+}
+
+/// An archive whose last file (Wav 7, eight bytes long) runs three bytes past the end of
+/// the archive, like one that was only partially downloaded.
+pub fn make_truncated_archive(game: DrsGameType) -> Vec<u8> {
+    let mut bytes = make_test_archive(game, &[(DrsFileType::Slp, 1, b"one"), (DrsFileType::Wav, 7, b"RIFFWAVE")]);
+    let len = bytes.len();
+    bytes.truncate(len - 3);
+    bytes
+}
+
+/// An archive assembled byte by byte, without the builder or writer, so that reader tests
+/// built on it don't just check the reader against the writer. It holds a Binary table
+/// with "palette" (ID 50500) and an SLP table with "one" (ID 1), packed without padding:
+/// the table headers follow the header, then the entries, then the data.
+#[cfg(test)]
+pub(crate) fn make_hand_built_archive(game: DrsGameType) -> Vec<u8> {
+    let (copyright, version, file_type): (&[u8], &[u8], &[u8]) = match game {
+        DrsGameType::AOE => (b"Copyright (c) 1997 Ensemble Studios.\x1a\0\0\0", b"1.00", b"tribe\0\0\0\0\0\0\0"),
+        DrsGameType::SWBG => (b"Copyright (c) 2001 LucasArts Entertainment Company LLC\x1a\0\0\0\0\0",
+                              b"1.00",
+                              b"swbg\0\0\0\0\0\0\0\0"),
+    };
+    let header_len = (copyright.len() + version.len() + file_type.len() + 8) as u32;
+    let data_start = header_len + 2 * 12 + 2 * 12;
+
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(copyright);
+    bytes.extend_from_slice(version);
+    bytes.extend_from_slice(file_type);
+    bytes.extend_from_slice(&2u32.to_le_bytes());
+    bytes.extend_from_slice(&data_start.to_le_bytes());
+    // Table headers: the type tag (stored reversed), where the entries are, and how many.
+    for &(tag, entries_at) in &[(b"anib", header_len + 24), (b" pls", header_len + 36)] {
+        bytes.extend_from_slice(tag);
+        bytes.extend_from_slice(&entries_at.to_le_bytes());
+        bytes.extend_from_slice(&1u32.to_le_bytes());
+    }
+    // Entries: file ID, offset, size.
+    for &(file_id, offset, size) in &[(50500u32, data_start, 7u32), (1, data_start + 7, 3)] {
+        bytes.extend_from_slice(&file_id.to_le_bytes());
+        bytes.extend_from_slice(&offset.to_le_bytes());
+        bytes.extend_from_slice(&size.to_le_bytes());
+    }
+    bytes.extend_from_slice(b"paletteone");
+    bytes
+}
+
+/// A file in the temporary directory holding an archive, which is removed when this is
+/// dropped. Dereferences to its path.
+#[cfg(test)]
+pub(crate) struct TempArchive {
+    path: PathBuf,
+}
+
+#[cfg(test)]
+impl Deref for TempArchive {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.path
+    }
+}
+
+#[cfg(test)]
+impl AsRef<Path> for TempArchive {
+    fn as_ref(&self) -> &Path {
+        &self.path
+    }
+}
+
+#[cfg(test)]
+impl Drop for TempArchive {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Writes an archive to a file of its own in the temporary directory, for tests of the
+/// APIs that take a path. The name only needs to be unique within the test suite.
+#[cfg(test)]
+pub(crate) fn write_temp_archive(name: &str, bytes: &[u8]) -> TempArchive {
+    let path = ::std::env::temp_dir().join(format!("chariot_drs_{}_{}", ::std::process::id(), name));
+    fs::write(&path, bytes).expect("failed to write temporary archive");
+    TempArchive { path }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::drs::DrsFile;
+
+    #[test]
+    fn hand_built_archive_reads_back() {
+        for &game in &[DrsGameType::AOE, DrsGameType::SWBG] {
+            let drs_file = DrsFile::from_bytes(&make_hand_built_archive(game)).unwrap();
+            drs_file.validate_integrity().unwrap();
+            assert_eq!(drs_file.header.game_type(), game);
+            assert_eq!(drs_file.header.serialized_len(), game.header_len());
+            assert_eq!(drs_file.find_file_with_type(50500).unwrap(), (DrsFileType::Binary, &b"palette".to_vec()));
+            assert_eq!(drs_file.find_file_with_type(1).unwrap(), (DrsFileType::Slp, &b"one".to_vec()));
+        }
+    }
+
+    #[test]
+    fn test_archive_matches_hand_built_archive() {
+        for &game in &[DrsGameType::AOE, DrsGameType::SWBG] {
+            let bytes = make_test_archive(game, &[(DrsFileType::Binary, 50500, b"palette"), (DrsFileType::Slp, 1, b"one")]);
+            assert_eq!(bytes, make_hand_built_archive(game));
+        }
+    }
+
+    #[test]
+    fn temp_archive_is_removed_when_dropped() {
+        let path = write_temp_archive("dropped.drs", b"");
+        let path_buf = path.to_path_buf();
+        assert!(path_buf.exists());
+        drop(path);
+        assert!(!path_buf.exists());
+    }
+
+    #[test]
+    fn test_archive_reads_back() {
+        let bytes = make_test_archive(DrsGameType::AOE,
+                                      &[(DrsFileType::Binary, 50500, b"palette"),
+                                        (DrsFileType::Wav, 7, b"RIFF"),
+                                        (DrsFileType::Binary, 50501, b"terrain")]);
+        let drs_file = DrsFile::from_bytes(&bytes).unwrap();
+        drs_file.validate_integrity().unwrap();
+        assert_eq!(drs_file.tables.len(), 2);
+        assert_eq!(drs_file.file_ids(), vec![50500, 50501, 7]);
+        assert_eq!(drs_file.find_file(50501).unwrap(), b"terrain");
+        assert_eq!(drs_file.find_file_with_type(7).unwrap().0, DrsFileType::Wav);
+    }
+
+    #[test]
+    fn test_archive_works_for_both_games() {
+        for &game in &[DrsGameType::AOE, DrsGameType::SWBG] {
+            let bytes = make_test_archive(game, &[(DrsFileType::Slp, 1, b"SLP")]);
+            let drs_file = DrsFile::from_bytes(&bytes).unwrap();
+            assert_eq!(drs_file.header.game_type(), game);
+            assert_eq!(drs_file.find_file(1).unwrap(), b"SLP");
+        }
+    }
+
+    #[test]
+    fn test_archive_reads_from_disk() {
+        let bytes = make_test_archive(DrsGameType::SWBG, &[(DrsFileType::Wav, 7, b"RIFF")]);
+        let path = write_temp_archive("fixture.drs", &bytes);
+        assert_eq!(DrsFile::read_from_file(&path).unwrap(), DrsFile::from_bytes(&bytes).unwrap());
+    }
+
+    #[test]
+    #[should_panic(expected = "duplicate file in test archive")]
+    fn test_archive_rejects_duplicates() {
+        make_test_archive(DrsGameType::AOE, &[(DrsFileType::Slp, 1, b"a"), (DrsFileType::Slp, 1, b"b")]);
+    }
+}
@@ -25,13 +25,55 @@
 #[macro_use]
 extern crate error_chain;
 
-extern crate chariot_io_tools;
-extern crate either;
-
+mod arena;
+#[cfg(feature = "tokio")]
+mod async_io;
+mod builder;
+mod cache;
+#[cfg(feature = "crc")]
+mod checksum;
+#[cfg(feature = "flate2")]
+mod compress;
+mod csv;
+mod dedup;
+mod diff;
 mod drs;
 mod error;
+mod extract;
+mod filesystem;
+#[cfg(any(test, feature = "test-fixtures"))]
+mod fixtures;
+#[cfg(feature = "mmap")]
+mod mmap;
+#[cfg(feature = "rayon")]
+mod parallel;
+mod reader;
+mod roundtrip;
+mod shared;
+mod sniff;
+mod writer;
 
-pub use drs::DrsFile;
-pub use drs::DrsFileType;
+pub use crate::arena::DrsArenaFile;
+pub use crate::builder::DrsBuilder;
+pub use crate::diff::DrsDiff;
+pub use crate::drs::{DrsFile, DrsFileRef};
+pub use crate::drs::DrsFileType;
+pub use crate::drs::{DrsReadOptions, OffsetWidth, UnknownTypeAction, UnknownTypeHandler};
+pub use crate::drs::find_embedded_headers;
+pub use crate::drs::{DrsCopyright, DrsGameType, DrsHeader, DrsHeaderMetadata, DrsLogicalTable, DrsTableEntry,
+              DrsTableHeader};
+pub use crate::extract::parse_file_name;
+pub use crate::filesystem::DrsFileSystem;
+#[cfg(feature = "test-fixtures")]
+pub use crate::fixtures::{make_empty_file_archive, make_test_archive, make_truncated_archive,
+                   make_zeroed_file_offset_archive};
+#[cfg(feature = "mmap")]
+pub use crate::mmap::DrsMmap;
+pub use crate::reader::{DrsArchive, DrsEntryReader, DrsRawTables, DrsReader};
+pub use crate::roundtrip::RoundTripReport;
+pub use crate::shared::SharedDrsFile;
+pub use crate::sniff::{detect_file_type, detect_game_type, detect_game_type_of_file, looks_like_shp, looks_like_slp,
+                looks_like_wav, slp_version, DrsGameGuess};
+pub use crate::writer::{DrsWriteOptions, EntryOrder};
 
-pub use error::{ChainErr, Error, ErrorKind, Result};
+pub use crate::error::{ChainErr, Error, ErrorKind, Result};
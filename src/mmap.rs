@@ -0,0 +1,171 @@
+// Chariot: An open source reimplementation of Age of Empires (1997)
+// Copyright (c) 2016 Kevin Fuller
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+//
+
+use crate::drs::{DrsFileType, DrsHeader, DrsTableEntry};
+use crate::error::*;
+use crate::reader::{build_id_map, build_index_map, read_raw_headers, DrsRawTables};
+use crate::writer::{write_archive, write_file, DrsWriteOptions, EntryOrder, TableSource};
+
+use memmap2::Mmap;
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::path::Path;
+
+/// A read-only DRS archive backed by a memory mapping of the file. Only the metadata is
+/// parsed up front; file contents are handed out as slices of the mapping without copying,
+/// which makes this well suited to renderers that touch many files over and over. It's
+/// the memory-mapped counterpart to `DrsArchive`, and `file_bytes` looks files up by ID
+/// alone in the same way.
+///
+/// As with any memory-mapped file, the archive must not be modified by another process
+/// while it's open.
+pub struct DrsMmap {
+    pub header: DrsHeader,
+    tables: DrsRawTables,
+    index_map: HashMap<(DrsFileType, u32), (usize, usize)>,
+    id_map: HashMap<u32, (usize, usize)>,
+    mmap: Mmap,
+}
+
+impl DrsMmap {
+    /// Opens and maps an archive, checking that every entry lies within the file and failing
+    /// with `TruncatedFile` like the other readers if one doesn't. Empty files are exempt,
+    /// since some tools leave their offsets pointing anywhere.
+    pub fn open<P: AsRef<Path>>(file_name: P) -> Result<DrsMmap> {
+        let file_name = file_name.as_ref();
+        let mut file = File::open(file_name)?;
+        let (header, tables) = read_raw_headers(&mut file, file_name)?;
+
+        // The mapping is only ever read, and the struct docs cover external modification.
+        let mmap = unsafe { Mmap::map(&file)? };
+        for (_, entries) in &tables {
+            for entry in entries {
+                entry.check_fits(mmap.len() as u64)?;
+            }
+        }
+
+        let index_map = build_index_map(&tables);
+        let id_map = build_id_map(&tables);
+        Ok(DrsMmap {
+            header,
+            tables,
+            index_map,
+            id_map,
+            mmap,
+        })
+    }
+
+    /// The table headers of the archive, each paired with the entries of that table.
+    pub fn tables(&self) -> &DrsRawTables {
+        &self.tables
+    }
+
+    /// Returns the contents of a file as a slice of the mapping, or None if the archive
+    /// doesn't contain it. The slice borrows from `self`, so it can't outlive the mapping.
+    /// Zero-length files produce an empty slice rather than None.
+    pub fn file(&self, file_type: DrsFileType, file_id: u32) -> Option<&[u8]> {
+        self.index_map
+            .get(&(file_type, file_id))
+            .map(|&(table_index, entry_index)| self.entry_contents(&self.tables[table_index].1[entry_index]))
+    }
+
+    /// Like `file`, but finds the file by ID alone, taking the earliest table that has it.
+    pub fn file_bytes(&self, file_id: u32) -> Option<&[u8]> {
+        self.id_map
+            .get(&file_id)
+            .map(|&(table_index, entry_index)| self.entry_contents(&self.tables[table_index].1[entry_index]))
+    }
+
+    fn entry_contents(&self, entry: &DrsTableEntry) -> &[u8] {
+        if entry.file_size == 0 {
+            return &[];
+        }
+        let start = entry.file_offset as usize;
+        &self.mmap[start..start + entry.file_size as usize]
+    }
+
+    /// Writes a copy of the archive to another file with the contents of some files
+    /// replaced, keyed by (file type, file ID). Unchanged files are written straight from
+    /// the mapping, so only the replacement contents need to be held in memory. Tables and
+    /// entries keep their current order, and every offset is recomputed. The destination
+    /// must not be the mapped archive itself.
+    pub fn write_modified<P: AsRef<Path>>(&self,
+                                          file_name: P,
+                                          edits: HashMap<(DrsFileType, u32), Vec<u8>>)
+                                          -> Result<()> {
+        for &(file_type, file_id) in edits.keys() {
+            if !self.index_map.contains_key(&(file_type, file_id)) {
+                return Err(ErrorKind::FileNotFound(file_type, file_id).into());
+            }
+        }
+
+        let sources: Vec<_> = self.tables
+            .iter()
+            .map(|(table_header, entries)| {
+                TableSource {
+                    file_type: table_header.file_type,
+                    files: entries.iter()
+                        .map(|entry| {
+                            let size = match edits.get(&(table_header.file_type, entry.file_id)) {
+                                Some(contents) => contents.len() as u64,
+                                None => entry.file_size,
+                            };
+                            (entry.file_id, size)
+                        })
+                        .collect(),
+                }
+            })
+            .collect();
+
+        let options = DrsWriteOptions {
+            entry_order: EntryOrder::AsInserted,
+            ..Default::default()
+        };
+        write_file(file_name.as_ref(), options.atomic, |out| {
+            write_archive(out, &self.header, &sources, &options, |table_index, entry_index| {
+                let (ref table_header, ref entries) = self.tables[table_index];
+                let entry = &entries[entry_index];
+                match edits.get(&(table_header.file_type, entry.file_id)) {
+                    Some(contents) => &contents[..],
+                    None => self.entry_contents(entry),
+                }
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::drs::DrsGameType;
+    use crate::fixtures::{make_truncated_archive, write_temp_archive};
+
+    #[test]
+    fn overrunning_entry_is_truncated_file() {
+        let path = write_temp_archive("truncated_mmap.drs", &make_truncated_archive(DrsGameType::AOE));
+        match DrsMmap::open(&path) {
+            Err(Error(ErrorKind::TruncatedFile { file_id: 7, expected: 8, available: 5 }, _)) => {},
+            other => panic!("expected TruncatedFile, got {:?}", other.map(|_| ())),
+        }
+    }
+}
@@ -0,0 +1,47 @@
+// Chariot: An open source reimplementation of Age of Empires (1997)
+// Copyright (c) 2016 Kevin Fuller
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+//
+use crate::drs::DrsFile;
+use crate::error::*;
+use crate::reader::pread_exact;
+
+use rayon::prelude::*;
+
+use std::fs::File;
+
+impl DrsFile {
+    /// Fills in the contents of every entry like `read_contents_from`, but reads the files
+    /// of each table in parallel with positioned reads. Contents stay in entry order.
+    pub(crate) fn read_contents_parallel(&mut self, file: &File) -> Result<()> {
+        self.check_contents_fit(file.metadata()?.len())?;
+        for table in &mut self.tables {
+            table.contents = table.entries
+                .par_iter()
+                .map(|entry| {
+                    let mut contents = vec![0u8; entry.file_size as usize];
+                    pread_exact(file, &mut contents, entry.file_offset)?;
+                    Ok(contents)
+                })
+                .collect::<Result<_>>()?;
+        }
+        self.finish_contents(&mut &*file)
+    }
+}
@@ -0,0 +1,457 @@
+// Chariot: An open source reimplementation of Age of Empires (1997)
+// Copyright (c) 2016 Kevin Fuller
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+//
+
+use crate::cache::read_cache;
+use crate::diff::DrsDiff;
+use crate::drs::{repair_file_offset, DrsFile, DrsFileContents, DrsFileType, DrsHeader, DrsTableEntry, DrsTableHeader,
+          OffsetWidth};
+use crate::error::*;
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
+
+/// Size of the buffers used when comparing file contents between two archives.
+const COMPARE_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Table headers paired with the entries belonging to each table, in archive order.
+pub type DrsRawTables = Vec<(DrsTableHeader, Vec<DrsTableEntry>)>;
+
+/// A DRS archive that has only had its header and table/entry metadata read.
+/// File contents stay on disk until they're asked for, which makes this a better
+/// fit than `DrsFile` when only a handful of files out of a large archive are needed.
+pub struct DrsReader {
+    pub header: DrsHeader,
+    tables: DrsRawTables,
+    index_map: HashMap<(DrsFileType, u32), (usize, usize)>,
+    file_name: PathBuf,
+    file: File,
+}
+
+impl DrsReader {
+    /// Opens a DRS archive and reads its metadata, leaving the file open for later reads.
+    pub fn open<P: AsRef<Path>>(file_name: P) -> Result<DrsReader> {
+        let file_name = file_name.as_ref();
+        let mut file = File::open(file_name)?;
+        let (header, tables) = read_raw_headers(&mut file, file_name)?;
+
+        let index_map = build_index_map(&tables);
+
+        Ok(DrsReader {
+            header,
+            tables,
+            index_map,
+            file_name: file_name.into(),
+            file,
+        })
+    }
+
+    /// Opens a DRS archive using metadata from a cache written by `DrsFile::entries_to_cache`
+    /// rather than parsing it from the archive, which is left unread until files are asked for.
+    pub fn open_with_cache<P: AsRef<Path>, R: Read>(file_name: P, cache: R) -> Result<DrsReader> {
+        let file_name = file_name.as_ref();
+        let (header, tables) = read_cache(cache)?;
+        let index_map = build_index_map(&tables);
+
+        Ok(DrsReader {
+            header,
+            tables,
+            index_map,
+            file_name: file_name.into(),
+            file: File::open(file_name)?,
+        })
+    }
+
+    /// The path this archive was opened from.
+    pub fn file_name(&self) -> &Path {
+        &self.file_name
+    }
+
+    /// The table headers of the archive, each paired with the entries of that table.
+    pub fn tables(&self) -> &[(DrsTableHeader, Vec<DrsTableEntry>)] {
+        &self.tables
+    }
+
+    /// Re-reads the archive header along with every table header and its entries, starting
+    /// from the beginning of the file. No lookup structures are built and no file contents
+    /// are read, so this is the cheapest way to get at an archive's raw metadata.
+    pub fn read_raw_headers(&mut self) -> Result<(DrsHeader, DrsRawTables)> {
+        read_raw_headers(&mut self.file, &self.file_name)
+    }
+
+    /// Looks up the entry for a file without reading any of its contents.
+    pub fn find_entry(&self, file_type: DrsFileType, file_id: u32) -> Option<&DrsTableEntry> {
+        self.index_map
+            .get(&(file_type, file_id))
+            .map(|&(table_index, entry_index)| &self.tables[table_index].1[entry_index])
+    }
+
+    /// Reads the contents of a single file from disk, or returns None if the archive
+    /// doesn't contain it.
+    pub fn read_file(&mut self, file_type: DrsFileType, file_id: u32) -> Result<Option<DrsFileContents>> {
+        match self.find_entry(file_type, file_id) {
+            Some(entry) => Ok(Some(read_entry(&self.file, entry)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Reads a file from disk in pieces of at most `chunk_size` bytes, one read per piece,
+    /// so only one chunk is held in memory at a time. Like `read_file_by_index`, this uses
+    /// positioned reads and leaves the shared file cursor alone. Returns None if the archive
+    /// doesn't contain the file, and panics if `chunk_size` is 0.
+    pub fn read_file_chunks(&self,
+                            file_type: DrsFileType,
+                            file_id: u32,
+                            chunk_size: usize)
+                            -> Option<impl Iterator<Item = Result<Vec<u8>>> + '_> {
+        assert!(chunk_size > 0, "chunk size must be non-zero");
+        let entry = self.find_entry(file_type, file_id)?;
+        let (file_offset, file_size) = (entry.file_offset, entry.file_size);
+        let chunk_size = chunk_size as u64;
+        let chunk_count = file_size.div_ceil(chunk_size);
+        Some((0..chunk_count).map(move |chunk_index| {
+            let start = chunk_index * chunk_size;
+            read_entry_at(&self.file, file_offset + start, chunk_size.min(file_size - start))
+        }))
+    }
+
+    /// The number of files in the archive across all tables.
+    pub fn num_files(&self) -> usize {
+        self.tables.iter().map(|t| t.1.len()).sum()
+    }
+
+    /// Reads the file at the given position in archive order (tables in order, then entries
+    /// in order), returning its type, ID, and contents. This uses positioned reads rather
+    /// than the shared file cursor, so it can be called from several threads at once.
+    pub fn read_file_by_index(&self, index: usize) -> Result<(DrsFileType, u32, DrsFileContents)> {
+        let mut remaining = index;
+        for (table_header, entries) in &self.tables {
+            if remaining < entries.len() {
+                let entry = &entries[remaining];
+                return Ok((table_header.file_type, entry.file_id, read_entry(&self.file, entry)?));
+            }
+            remaining -= entries.len();
+        }
+        Err(ErrorKind::FileIndexOutOfRange(index, self.num_files()).into())
+    }
+
+    /// Compares this archive against another one without loading either into memory.
+    /// Entry metadata is compared first, and contents are only read (in small chunks)
+    /// for files whose sizes match, to catch same-size edits.
+    pub fn diff_lazy(&mut self, other: &mut DrsReader) -> Result<DrsDiff> {
+        let mut diff = DrsDiff::new();
+        let mut same_size = Vec::new();
+
+        for (table_header, entries) in &self.tables {
+            for entry in entries {
+                let key = (table_header.file_type, entry.file_id);
+                match other.find_entry(key.0, key.1) {
+                    None => diff.removed.push(key),
+                    Some(other_entry) if other_entry.file_size != entry.file_size => diff.modified.push(key),
+                    Some(other_entry) => {
+                        same_size.push((key, entry.file_offset, other_entry.file_offset, entry.file_size))
+                    }
+                }
+            }
+        }
+        for (table_header, entries) in &other.tables {
+            for entry in entries {
+                if self.find_entry(table_header.file_type, entry.file_id).is_none() {
+                    diff.added.push((table_header.file_type, entry.file_id));
+                }
+            }
+        }
+
+        let mut buffer = vec![0u8; COMPARE_CHUNK_SIZE];
+        let mut other_buffer = vec![0u8; COMPARE_CHUNK_SIZE];
+        for (key, offset, other_offset, file_size) in same_size {
+            let mut compared = 0usize;
+            while compared < file_size as usize {
+                let len = COMPARE_CHUNK_SIZE.min(file_size as usize - compared);
+                self.file.seek(SeekFrom::Start(offset + compared as u64))?;
+                self.file.read_exact(&mut buffer[..len])?;
+                other.file.seek(SeekFrom::Start(other_offset + compared as u64))?;
+                other.file.read_exact(&mut other_buffer[..len])?;
+                if buffer[..len] != other_buffer[..len] {
+                    diff.modified.push(key);
+                    break;
+                }
+                compared += len;
+            }
+        }
+
+        Ok(diff)
+    }
+}
+
+/// A lazily-read DRS archive addressed by file ID alone, for callers that don't care which
+/// table a file lives in. Only metadata is read up front; each `read_file` call reads just
+/// that file's bytes with a positioned read, so it only needs `&self`.
+pub struct DrsArchive {
+    reader: DrsReader,
+    id_map: HashMap<u32, (usize, usize)>,
+}
+
+impl DrsArchive {
+    /// Opens a DRS archive and reads its metadata, leaving the file open for later reads.
+    pub fn open<P: AsRef<Path>>(file_name: P) -> Result<DrsArchive> {
+        Ok(DrsArchive::from_reader(DrsReader::open(file_name)?))
+    }
+
+    /// Wraps an already-open `DrsReader`.
+    pub fn from_reader(reader: DrsReader) -> DrsArchive {
+        let id_map = build_id_map(&reader.tables);
+        DrsArchive { reader, id_map }
+    }
+
+    pub fn header(&self) -> &DrsHeader {
+        &self.reader.header
+    }
+
+    /// The table headers of the archive, each paired with the entries of that table.
+    pub fn tables(&self) -> &[(DrsTableHeader, Vec<DrsTableEntry>)] {
+        self.reader.tables()
+    }
+
+    /// Looks up the type and entry of a file without reading any of its contents.
+    pub fn find_entry(&self, file_id: u32) -> Option<(DrsFileType, &DrsTableEntry)> {
+        self.id_map.get(&file_id).map(|&(table_index, entry_index)| {
+            let (table_header, entries) = &self.reader.tables[table_index];
+            (table_header.file_type, &entries[entry_index])
+        })
+    }
+
+    /// Reads the contents of a single file from disk. Fails with `FileIdNotFound`, without
+    /// touching the file, if the archive has no file with that ID.
+    pub fn read_file(&self, file_id: u32) -> Result<DrsFileContents> {
+        let (_, entry) = self.find_entry(file_id).ok_or(ErrorKind::FileIdNotFound(file_id))?;
+        read_entry(&self.reader.file, entry)
+    }
+
+    /// Opens a single file for streaming rather than reading it all at once. Fails with
+    /// `FileIdNotFound` if the archive has no file with that ID.
+    pub fn open_file(&self, file_id: u32) -> Result<DrsEntryReader<'_>> {
+        let (_, entry) = self.find_entry(file_id).ok_or(ErrorKind::FileIdNotFound(file_id))?;
+        check_entry_fits(&self.reader.file, entry)?;
+        Ok(DrsEntryReader {
+            file: &self.reader.file,
+            start: entry.file_offset,
+            len: entry.file_size,
+            position: 0,
+        })
+    }
+
+    /// Gives back the underlying reader, for lookups by file type as well as ID.
+    pub fn into_reader(self) -> DrsReader {
+        self.reader
+    }
+}
+
+/// Reads and seeks within the bytes of a single file in an archive, as if they were a file
+/// of their own. Reads stop at the end of the file rather than running into whatever
+/// follows it, and seeks are clamped to the file's bounds. Reads are positioned, so several
+/// of these can be used at once over the same archive.
+pub struct DrsEntryReader<'a> {
+    file: &'a File,
+    start: u64,
+    len: u64,
+    position: u64,
+}
+
+impl<'a> DrsEntryReader<'a> {
+    /// The length of the file being read.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+impl<'a> Read for DrsEntryReader<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let remaining = self.len - self.position;
+        let want = (buf.len() as u64).min(remaining) as usize;
+        if want == 0 {
+            return Ok(0);
+        }
+        let read = pread(self.file, &mut buf[..want], self.start + self.position)?;
+        self.position += read as u64;
+        Ok(read)
+    }
+}
+
+impl<'a> Seek for DrsEntryReader<'a> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(offset) => offset as i128,
+            SeekFrom::End(offset) => self.len as i128 + offset as i128,
+            SeekFrom::Current(offset) => self.position as i128 + offset as i128,
+        };
+        self.position = target.clamp(0, self.len as i128) as u64;
+        Ok(self.position)
+    }
+}
+
+/// Maps each (file type, file ID) to the (table index, entry index) of its entry.
+/// Later entries win if an ID appears more than once, as with `DrsLogicalTable`.
+pub(crate) fn build_index_map(tables: &DrsRawTables) -> HashMap<(DrsFileType, u32), (usize, usize)> {
+    let mut index_map = HashMap::new();
+    for (table_index, (table_header, entries)) in tables.iter().enumerate() {
+        for (entry_index, entry) in entries.iter().enumerate() {
+            index_map.insert((table_header.file_type, entry.file_id), (table_index, entry_index));
+        }
+    }
+    index_map
+}
+
+/// Maps each file ID to the (table index, entry index) of its entry, for lookups that
+/// ignore the file type. If an ID appears in more than one table, the earliest table wins.
+pub(crate) fn build_id_map(tables: &DrsRawTables) -> HashMap<u32, (usize, usize)> {
+    let mut id_map = HashMap::new();
+    for (table_index, (_, entries)) in tables.iter().enumerate() {
+        for (entry_index, entry) in entries.iter().enumerate() {
+            id_map.entry(entry.file_id).or_insert((table_index, entry_index));
+        }
+    }
+    id_map
+}
+
+pub(crate) fn read_raw_headers(file: &mut File, file_name: &Path) -> Result<(DrsHeader, DrsRawTables)> {
+    file.seek(SeekFrom::Start(0))?;
+    let mut header = DrsHeader::read_from_file(file, file_name)?;
+    let (table_headers, _) = DrsFile::read_table_headers(file, header.table_count, None)?;
+    let table_entries = DrsFile::read_file_entry_headers(file, &table_headers, OffsetWidth::Standard)?;
+    repair_file_offset(file, &mut header, &table_headers, OffsetWidth::Standard)?;
+    Ok((header, table_headers.into_iter().zip(table_entries).collect()))
+}
+
+/// Reads an entry's contents with a positioned read. The entry is checked against the
+/// length of the file first, so a corrupt size fails with `TruncatedFile` instead of
+/// asking for an enormous buffer.
+fn read_entry(file: &File, entry: &DrsTableEntry) -> Result<DrsFileContents> {
+    check_entry_fits(file, entry)?;
+    read_entry_at(file, entry.file_offset, entry.file_size)
+}
+
+/// Reads exactly `size` bytes starting at `offset` with a positioned read, so the file's
+/// cursor is left alone. Fails with `ShortRead` if the archive ends first, which can still
+/// happen after `check_entry_fits` if the file is truncated while it's open.
+fn read_entry_at(file: &File, offset: u64, size: u64) -> Result<Vec<u8>> {
+    let mut contents = vec![0u8; size as usize];
+    match pread_exact(file, &mut contents, offset) {
+        Ok(()) => Ok(contents),
+        Err(ref err) if err.kind() == io::ErrorKind::UnexpectedEof => Err(ErrorKind::ShortRead { offset, size }.into()),
+        Err(err) => Err(err.into()),
+    }
+}
+
+fn check_entry_fits(file: &File, entry: &DrsTableEntry) -> Result<()> {
+    entry.check_fits(file.metadata()?.len())
+}
+
+/// Reads from the given offset without moving the file's cursor.
+#[cfg(unix)]
+fn pread(file: &File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+    use std::os::unix::fs::FileExt;
+    file.read_at(buf, offset)
+}
+
+/// Reads from the given offset; see `pread_exact` for how this differs from Unix.
+#[cfg(windows)]
+fn pread(file: &File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+    use std::os::windows::fs::FileExt;
+    file.seek_read(buf, offset)
+}
+
+/// Fills the buffer from the given offset without moving the file's cursor.
+#[cfg(unix)]
+pub(crate) fn pread_exact(file: &File, buf: &mut [u8], offset: u64) -> io::Result<()> {
+    use std::os::unix::fs::FileExt;
+    file.read_exact_at(buf, offset)
+}
+
+/// Fills the buffer from the given offset. Windows has no read that leaves the cursor
+/// alone, but `seek_read` doesn't depend on where the cursor was, which is what matters.
+#[cfg(windows)]
+pub(crate) fn pread_exact(file: &File, mut buf: &mut [u8], mut offset: u64) -> io::Result<()> {
+    use std::os::windows::fs::FileExt;
+    while !buf.is_empty() {
+        match file.seek_read(buf, offset) {
+            Ok(0) => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "failed to fill whole buffer")),
+            Ok(read) => {
+                let rest = buf;
+                buf = &mut rest[read..];
+                offset += read as u64;
+            },
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => {},
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(())
+}
+
+/// Serializes the seek-then-read fallback below. Without a positioned read the cursor is
+/// shared state, and `parallel` reads one file from several threads at once.
+#[cfg(not(any(unix, windows)))]
+static SEEK_READ_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+/// Reads from the given offset by seeking first, for targets without a positioned read.
+/// This does move the file's cursor, so it's never relied on staying put between reads.
+#[cfg(not(any(unix, windows)))]
+fn pread(mut file: &File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+    let _guard = SEEK_READ_LOCK.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    file.seek(SeekFrom::Start(offset))?;
+    file.read(buf)
+}
+
+/// Fills the buffer from the given offset by seeking first; see `pread` above.
+#[cfg(not(any(unix, windows)))]
+pub(crate) fn pread_exact(mut file: &File, buf: &mut [u8], offset: u64) -> io::Result<()> {
+    let _guard = SEEK_READ_LOCK.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::drs::DrsGameType;
+    use crate::fixtures::{make_truncated_archive, write_temp_archive};
+
+    #[test]
+    fn overrunning_entry_is_truncated_file() {
+        let path = write_temp_archive("truncated_reader.drs", &make_truncated_archive(DrsGameType::AOE));
+        let archive = DrsArchive::open(&path).unwrap();
+        assert_eq!(archive.read_file(1).unwrap(), b"one");
+        for result in [archive.read_file(7).map(|_| ()), archive.open_file(7).map(|_| ())] {
+            match result {
+                Err(Error(ErrorKind::TruncatedFile { file_id: 7, expected: 8, available: 5 }, _)) => {},
+                other => panic!("expected TruncatedFile, got {:?}", other),
+            }
+        }
+    }
+}
@@ -0,0 +1,164 @@
+// Chariot: An open source reimplementation of Age of Empires (1997)
+// Copyright (c) 2016 Kevin Fuller
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+//
+
+use crate::drs::{known_file_type, read_u32_at, stream_len, DrsFile, DrsFileType, DrsHeader, DrsTableHeader, OffsetWidth};
+use crate::error::*;
+use crate::writer::{compute_layout, DrsWriteOptions, TableSource};
+
+use std::fs::File;
+use std::io::Seek;
+use std::path::Path;
+
+/// The parts of an archive that reading it and writing it back out with the default
+/// `DrsWriteOptions` would not preserve byte for byte.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RoundTripReport {
+    /// Type tags of tables this crate doesn't recognize. These are only read with an
+    /// `UnknownTypeHandler` that keeps them as `DrsFileType::Unknown`, which writes them
+    /// back out with the same tag, so they don't make the archive unsafe by themselves.
+    pub unknown_types: Vec<u32>,
+    /// The number of bytes after the end of the last file's data, which are dropped.
+    pub trailing_bytes: u64,
+    /// True if the header's `file_offset` differs from where the file data would start.
+    pub header_offset_changed: bool,
+    /// Indices of tables whose entries aren't where the writer would put them.
+    pub moved_tables: Vec<usize>,
+    /// Files whose entries would move within their table (the writer sorts by file ID) or
+    /// whose data would land at a different offset, due to gaps, overlapping files, or
+    /// alignment other than 4 bytes.
+    pub moved_files: Vec<(DrsFileType, u32)>,
+}
+
+impl RoundTripReport {
+    /// True if rewriting the archive would reproduce it exactly, given that any unknown
+    /// types are read with `UnknownTypeAction::AsOther`.
+    pub fn is_safe(&self) -> bool {
+        self.trailing_bytes == 0 && !self.header_offset_changed && self.moved_tables.is_empty() &&
+        self.moved_files.is_empty()
+    }
+}
+
+impl DrsFile {
+    /// Reads the headers of an archive and reports everything that would be lost or changed
+    /// if it were loaded and written back out, so that tools can refuse to rewrite archives
+    /// they'd alter. Only the metadata is read; file contents are never compared.
+    pub fn is_round_trip_safe<P: AsRef<Path>>(file_name: P) -> Result<RoundTripReport> {
+        let file_name = file_name.as_ref();
+        let mut file = File::open(file_name)?;
+        let header = DrsHeader::read_from_file(&mut file, file_name)?;
+
+        let mut report = RoundTripReport::default();
+        let mut table_headers = Vec::new();
+        for _table_index in 0..header.table_count {
+            let binary_val = read_u32_at(&mut file, "table file type")?;
+            let file_type = match known_file_type(binary_val) {
+                Some(file_type) => file_type,
+                None => {
+                    report.unknown_types.push(binary_val);
+                    DrsFileType::Unknown(binary_val)
+                }
+            };
+            table_headers.push(DrsTableHeader {
+                file_type,
+                table_offset: read_u32_at(&mut file, "table offset")?,
+                file_count: read_u32_at(&mut file, "table file count")?,
+            });
+        }
+        let table_entries = DrsFile::read_file_entry_headers(&mut file, &table_headers, OffsetWidth::Standard)?;
+        let metadata_end = file.stream_position()?;
+        let data_end = table_entries.iter()
+            .flat_map(|entries| entries.iter())
+            .filter(|e| e.file_size > 0)
+            .map(|e| e.file_offset + e.file_size)
+            .fold(metadata_end, u64::max);
+        report.trailing_bytes = stream_len(&mut file)?.saturating_sub(data_end);
+
+        let sources: Vec<_> = table_headers.iter()
+            .zip(&table_entries)
+            .map(|(table_header, entries)| {
+                TableSource {
+                    file_type: table_header.file_type,
+                    files: entries.iter().map(|e| (e.file_id, e.file_size)).collect(),
+                }
+            })
+            .collect();
+        let layout = compute_layout(&header, &sources, &DrsWriteOptions::default())?;
+
+        report.header_offset_changed = layout.header.file_offset != header.file_offset;
+        // The writer may reorder tables (by type, under `EntryOrder::ById`), so each one is
+        // compared with the table it was laid out from rather than whatever was in its place.
+        for (position, table) in layout.tables.iter().enumerate() {
+            if table.source != position || table.header.table_offset != table_headers[table.source].table_offset {
+                report.moved_tables.push(table.source);
+            }
+            let original = &table_entries[table.source];
+            for (position, (entry, &index)) in table.entries.iter().zip(&table.order).enumerate() {
+                if position != index || entry.file_offset != original[index].file_offset {
+                    report.moved_files.push((table.header.file_type, entry.file_id));
+                }
+            }
+        }
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::drs::{DrsGameType, DrsReadOptions, UnknownTypeAction};
+    use crate::fixtures::{make_test_archive, write_temp_archive};
+
+    #[test]
+    fn unknown_types_survive_a_round_trip() {
+        let files: [(DrsFileType, u32, &[u8]); 2] = [(DrsFileType::Slp, 1, b"slp!"),
+                                                     (DrsFileType::Unknown(0x7A7A7A7A), 2, b"zzzz")];
+        let bytes = make_test_archive(DrsGameType::AOE, &files);
+        let path = write_temp_archive("unknown_round_trip.drs", &bytes);
+
+        let report = DrsFile::is_round_trip_safe(&path).unwrap();
+        assert_eq!(report.unknown_types, [0x7A7A7A7A]);
+        assert!(report.is_safe(), "{:?}", report);
+
+        let options = DrsReadOptions {
+            unknown_type_handler: Some(Box::new(|_| UnknownTypeAction::AsOther)),
+            ..DrsReadOptions::default()
+        };
+        let mut rewritten = Vec::new();
+        DrsFile::read_from_file_with_options(&path, options)
+            .unwrap()
+            .write_to_with_options(&mut rewritten, &DrsWriteOptions::default())
+            .unwrap();
+        assert_eq!(rewritten, bytes);
+    }
+
+    #[test]
+    fn reordered_tables_are_reported_as_moved() {
+        // Wav sorts after Slp, so the default writer swaps these tables, and their data too.
+        let files: [(DrsFileType, u32, &[u8]); 2] = [(DrsFileType::Wav, 7, b"RIFF"), (DrsFileType::Slp, 1, b"slp!")];
+        let path = write_temp_archive("reordered_round_trip.drs", &make_test_archive(DrsGameType::AOE, &files));
+
+        let report = DrsFile::is_round_trip_safe(&path).unwrap();
+        assert_eq!(report.moved_tables, [1, 0]);
+        assert_eq!(report.moved_files, [(DrsFileType::Slp, 1), (DrsFileType::Wav, 7)]);
+        assert!(!report.is_safe());
+    }
+}
@@ -0,0 +1,92 @@
+// Chariot: An open source reimplementation of Age of Empires (1997)
+// Copyright (c) 2016 Kevin Fuller
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+//
+
+use crate::drs::{DrsFile, DrsFileType, DrsHeader};
+use crate::reader::DrsRawTables;
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// A read-only archive for sharing between threads. Every file's contents live in their
+/// own `Arc<[u8]>`, so handing a file out is a reference count increment rather than a
+/// copy, and it stays alive after the archive itself is dropped. This is `Send + Sync`.
+pub struct SharedDrsFile {
+    pub header: DrsHeader,
+    tables: DrsRawTables,
+    contents: HashMap<(DrsFileType, u32), Arc<[u8]>>,
+}
+
+impl SharedDrsFile {
+    /// The table headers of the archive, each paired with the entries of that table.
+    pub fn tables(&self) -> &DrsRawTables {
+        &self.tables
+    }
+
+    /// Returns a handle to a file's contents, or None if the archive doesn't contain it.
+    pub fn find_file(&self, file_type: DrsFileType, file_id: u32) -> Option<Arc<[u8]>> {
+        self.contents.get(&(file_type, file_id)).cloned()
+    }
+}
+
+impl DrsFile {
+    /// Converts the archive into a `SharedDrsFile`. The contents are moved, not copied.
+    pub fn into_shared(self) -> Arc<SharedDrsFile> {
+        let mut tables = Vec::with_capacity(self.tables.len());
+        let mut contents = HashMap::new();
+        for table in self.tables {
+            let file_type = table.header.file_type;
+            for (entry, file_contents) in table.entries.iter().zip(table.contents) {
+                contents.insert((file_type, entry.file_id), Arc::from(file_contents));
+            }
+            tables.push((table.header, table.entries));
+        }
+        Arc::new(SharedDrsFile {
+            header: self.header,
+            tables,
+            contents,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::drs::DrsGameType;
+    use crate::fixtures::make_hand_built_archive;
+
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    fn shared_archive_is_send_and_sync() {
+        assert_send_sync::<SharedDrsFile>();
+        assert_send_sync::<Arc<SharedDrsFile>>();
+    }
+
+    #[test]
+    fn contents_outlive_the_shared_archive() {
+        let shared = DrsFile::from_bytes(&make_hand_built_archive(DrsGameType::AOE)).unwrap().into_shared();
+        let palette = shared.find_file(DrsFileType::Binary, 50500).unwrap();
+        assert!(shared.find_file(DrsFileType::Slp, 50500).is_none());
+        drop(shared);
+        assert_eq!(&palette[..], b"palette");
+    }
+}
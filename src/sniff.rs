@@ -0,0 +1,168 @@
+// Chariot: An open source reimplementation of Age of Empires (1997)
+// Copyright (c) 2016 Kevin Fuller
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+//
+
+use crate::drs::{detect_header_layout, DrsFile, DrsFileContents, DrsFileType, DrsGameType, TABLE_ENTRY_LEN,
+                 TABLE_HEADER_LEN};
+use crate::error::*;
+
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::SeekFrom;
+use std::path::Path;
+
+/// True if the bytes start with an SLP version string ("2.0N" in the original games,
+/// "3.0" or "4.x" in later releases).
+pub fn looks_like_slp(bytes: &[u8]) -> bool {
+    bytes.len() >= 4 && (bytes[0] == b'2' || bytes[0] == b'3' || bytes[0] == b'4') && bytes[1] == b'.' &&
+    (bytes[2] as char).is_ascii_digit()
+}
+
+/// Returns the 4-byte version field at the start of an SLP file (i.e., "2.0N"),
+/// or None if the bytes don't look like an SLP.
+pub fn slp_version(bytes: &[u8]) -> Option<[u8; 4]> {
+    if !looks_like_slp(bytes) {
+        return None;
+    }
+    let mut version = [0u8; 4];
+    version.copy_from_slice(&bytes[0..4]);
+    Some(version)
+}
+
+/// True if the bytes start with the SHP version string ("1.10").
+pub fn looks_like_shp(bytes: &[u8]) -> bool {
+    bytes.starts_with(b"1.10")
+}
+
+/// True if the bytes start with a RIFF header describing WAVE data.
+pub fn looks_like_wav(bytes: &[u8]) -> bool {
+    bytes.len() >= 12 && &bytes[0..4] == b"RIFF" && &bytes[8..12] == b"WAVE"
+}
+
+/// Works out which game an archive was made for without reading any more of it than needed.
+/// This reads at most the first 76 bytes of the stream and checks them against each game's
+/// header layout: SWBG if bytes 0..60 hold the SWBG copyright and bytes 64..76 its "swbg"
+/// file type, otherwise AOE if bytes 0..40 hold the AOE copyright and bytes 44..56 its
+/// "tribe" file type. Trailing padding in those fields is ignored. Fails with `InvalidDrs`
+/// if neither layout matches. The reader is left where it was.
+pub fn detect_game_type<R: Read + Seek>(reader: &mut R) -> Result<DrsGameType> {
+    detect_game_type_named(reader, Path::new("<stream>"))
+}
+
+/// Like `detect_game_type`, for an archive on disk.
+pub fn detect_game_type_of_file<P: AsRef<Path>>(file_name: P) -> Result<DrsGameType> {
+    let file_name = file_name.as_ref();
+    detect_game_type_named(&mut File::open(file_name)?, file_name)
+}
+
+fn detect_game_type_named<R: Read + Seek>(reader: &mut R, file_name: &Path) -> Result<DrsGameType> {
+    let start = reader.stream_position()?;
+    let detected = detect_header_layout(reader, file_name, true);
+    reader.seek(SeekFrom::Start(start))?;
+    detected
+}
+
+/// The outcome of `DrsFile::guess_game_from_structure`.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum DrsGameGuess {
+    /// Every table's offset is consistent with this game's header length, and not the other's.
+    Likely(DrsGameType),
+    /// The table layout doesn't point to either game.
+    Unknown,
+}
+
+/// Works out a file's type from its contents alone. Returns None when no known signature
+/// matches, which is the case for most binary files since they have no common magic.
+pub fn detect_file_type(bytes: &[u8]) -> Option<DrsFileType> {
+    if looks_like_wav(bytes) {
+        Some(DrsFileType::Wav)
+    } else if looks_like_shp(bytes) {
+        Some(DrsFileType::Shp)
+    } else if looks_like_slp(bytes) {
+        Some(DrsFileType::Slp)
+    } else {
+        None
+    }
+}
+
+impl DrsFile {
+    /// Looks up a file and reports the type its contents actually have, which may differ
+    /// from the table it was found in if the file was misfiled. Files without a
+    /// recognizable signature are reported with the type of the table they're in.
+    pub fn file_with_detected_type(&self,
+                                   file_type: DrsFileType,
+                                   file_id: u32)
+                                   -> Option<(DrsFileType, &DrsFileContents)> {
+        self.find_table(file_type)
+            .and_then(|table| table.find_file_contents(file_id))
+            .map(|contents| (detect_file_type(contents).unwrap_or(file_type), contents))
+    }
+
+    /// Guesses which game the archive was made for without trusting its header strings,
+    /// for recovering archives whose copyright and type fields are both damaged. This is a
+    /// heuristic: it relies on the header having a different length for each game (64
+    /// bytes for AOE, 84 for SWBG), so the table headers and entry lists that follow it
+    /// start at offsets characteristic of the game. It only looks at the table offsets as
+    /// read, and returns `Unknown` when there are no tables or they don't agree.
+    pub fn guess_game_from_structure(&self) -> DrsGameGuess {
+        let candidates = [DrsGameType::AOE, DrsGameType::SWBG];
+        let mut matches = candidates.iter().filter(|game_type| {
+            let mut table_offset = game_type.header_len() + TABLE_HEADER_LEN * self.tables.len() as u64;
+            !self.tables.is_empty() &&
+            self.tables.iter().all(|table| {
+                let expected = table_offset;
+                table_offset += TABLE_ENTRY_LEN * table.entries.len() as u64;
+                table.header.table_offset as u64 == expected
+            })
+        });
+        match (matches.next(), matches.next()) {
+            (Some(&game_type), None) => DrsGameGuess::Likely(game_type),
+            _ => DrsGameGuess::Unknown,
+        }
+    }
+
+    /// Lists the ID and SLP version of every file in the archive's SLP tables, in archive
+    /// order. Files in those tables that don't look like SLPs are left out.
+    pub fn slp_versions(&self) -> Vec<(u32, [u8; 4])> {
+        self.tables
+            .iter()
+            .filter(|t| t.header.file_type == DrsFileType::Slp)
+            .flat_map(|t| t.entries.iter().zip(&t.contents))
+            .filter_map(|(entry, contents)| slp_version(contents).map(|version| (entry.file_id, version)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::make_test_archive;
+
+    #[test]
+    fn guesses_game_from_table_offsets() {
+        for &game_type in &[DrsGameType::AOE, DrsGameType::SWBG] {
+            let files: [(DrsFileType, u32, &[u8]); 2] = [(DrsFileType::Slp, 1, b"one"), (DrsFileType::Wav, 2, b"RIFF")];
+            let drs_file = DrsFile::from_bytes(&make_test_archive(game_type, &files)).unwrap();
+            assert_eq!(drs_file.guess_game_from_structure(), DrsGameGuess::Likely(game_type));
+        }
+        assert_eq!(DrsFile::empty().guess_game_from_structure(), DrsGameGuess::Unknown);
+    }
+}
@@ -0,0 +1,483 @@
+// Chariot: An open source reimplementation of Age of Empires (1997)
+// Copyright (c) 2016 Kevin Fuller
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+//
+
+use crate::drs::{metadata_end, to_u32, DrsFile, DrsFileType, DrsHeader, DrsTableEntry, DrsTableHeader, BUILD_TAG_MAGIC,
+                 TABLE_ENTRY_LEN, TABLE_HEADER_LEN};
+use crate::error::*;
+
+use chariot_io_tools::WriteExt;
+
+use std::collections::HashSet;
+use std::fs;
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::BufWriter;
+use std::path::Path;
+use std::process;
+
+/// Options controlling how a `DrsFile` is laid out when it's written.
+#[derive(Debug, Clone)]
+pub struct DrsWriteOptions {
+    /// Pads the start of each file's data with null bytes to a multiple of this many bytes.
+    /// The original Age of Empires archives align file data to 4 bytes, and some loaders
+    /// assume it, so `Some(4)` is the default. `None` (or 0/1) packs files back-to-back.
+    pub align: Option<u32>,
+    /// The order tables and the entries within each table are written in. File contents
+    /// are laid out in the same order, so entry offsets always ascend.
+    pub entry_order: EntryOrder,
+    /// Only tables of these types are written, with the header's table count and all
+    /// offsets computed for that subset. `None` writes every table.
+    pub include_types: Option<HashSet<DrsFileType>>,
+    /// Writes to a temporary file next to the destination and renames it into place once
+    /// it's complete, so a failed write never leaves a truncated archive behind (or
+    /// destroys the one being replaced). Only affects the methods that write to a path.
+    pub atomic: bool,
+    /// Appends a trailer recording this string (e.g. a build ID or timestamp) after the file
+    /// data, where the game's loader ignores it. `DrsFile::build_tag` reads it back.
+    pub build_tag: Option<String>,
+}
+
+impl Default for DrsWriteOptions {
+    fn default() -> DrsWriteOptions {
+        DrsWriteOptions {
+            align: Some(4),
+            entry_order: EntryOrder::ById,
+            include_types: None,
+            atomic: true,
+            build_tag: None,
+        }
+    }
+}
+
+/// How tables and the entries of each table are ordered when an archive is written.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum EntryOrder {
+    /// Tables ascending by their type tag and entries ascending by file ID, matching the
+    /// original game archives. The engine reads files sequentially in this order, so this
+    /// is the default. Both sorts are stable, so the same files produce the same bytes
+    /// whatever order they were added in, which keeps rebuilt archives diffable.
+    ById,
+    /// Tables in the order they're in within the `DrsFile`, and entries in the order
+    /// they're in within each `DrsLogicalTable`.
+    AsInserted,
+}
+
+/// The headers of an archive as they'll be written, with every count, offset, and size
+/// recomputed from the in-memory data rather than copied from what was read.
+pub(crate) struct DrsLayout {
+    pub header: DrsHeader,
+    pub tables: Vec<DrsTableLayout>,
+}
+
+pub(crate) struct DrsTableLayout {
+    pub header: DrsTableHeader,
+    pub entries: Vec<DrsTableEntry>,
+    /// Index of the `TableSource` this was laid out from.
+    pub source: usize,
+    /// For each written entry, the index of its file in the `TableSource`.
+    pub order: Vec<usize>,
+}
+
+impl DrsFile {
+    /// The smallest an archive holding these files can be written as: the header, table
+    /// headers, and entries, followed by every file's contents packed back to back. This is
+    /// exactly the size written with `align: None` and without a build tag.
+    pub fn min_encoded_len(&self) -> u64 {
+        let contents_len: u64 = self.tables
+            .iter()
+            .flat_map(|t| t.contents.iter())
+            .map(|contents| contents.len() as u64)
+            .sum();
+        metadata_end(self.header.serialized_len(),
+                     self.tables.iter().map(|t| t.entries.len() as u64),
+                     TABLE_ENTRY_LEN) + contents_len
+    }
+
+    /// The largest the archive can be when written with the given alignment, assuming the
+    /// worst case of every file needing `align - 1` bytes of padding. The size written with
+    /// `DrsWriteOptions { align: Some(align), .. }` (and no build tag) always falls between
+    /// `min_encoded_len` and this.
+    pub fn max_encoded_len(&self, align: u32) -> u64 {
+        let file_count: u64 = self.tables.iter().map(|t| t.contents.len() as u64).sum();
+        self.min_encoded_len() + align.saturating_sub(1) as u64 * file_count
+    }
+
+    /// Writes the archive to the given file with the default options, creating or replacing
+    /// it. Every table offset, file offset, and file size is computed from the tables in
+    /// memory rather than copied from the headers, so the result reads back with
+    /// `read_from_file` to the same files and contents.
+    pub fn write_to_file<P: AsRef<Path>>(&self, file_name: P) -> Result<()> {
+        self.write_to_file_with_options(file_name, &DrsWriteOptions::default())
+    }
+
+    /// Writes the archive to the given file, creating or truncating it.
+    pub fn write_to_file_with_options<P: AsRef<Path>>(&self, file_name: P, options: &DrsWriteOptions) -> Result<()> {
+        write_file(file_name.as_ref(), options.atomic, |out| self.write_to_with_options(out, options))
+    }
+
+    /// Writes the archive in the layout `read_from_file` expects: the header, all of the
+    /// table headers, each table's entries, and then the contents of every file.
+    ///
+    /// Everything is written to `out` as it's produced, with file contents copied straight
+    /// from the tables one at a time, so the only memory used beyond the archive itself is
+    /// the layout metadata (a few bytes per entry). No serialized copy is ever assembled.
+    pub fn write_to_with_options<W: Write>(&self, out: &mut W, options: &DrsWriteOptions) -> Result<()> {
+        let mut sources = Vec::with_capacity(self.tables.len());
+        for (table_index, table) in self.tables.iter().enumerate() {
+            if table.entries.len() != table.contents.len() {
+                let reason = format!("{} entries but {} files", table.entries.len(), table.contents.len());
+                return Err(ErrorKind::InconsistentTable(table_index, reason).into());
+            }
+            sources.push(TableSource {
+                file_type: table.header.file_type,
+                files: table.entries
+                    .iter()
+                    .zip(&table.contents)
+                    .map(|(entry, contents)| (entry.file_id, contents.len() as u64))
+                    .collect(),
+            });
+        }
+
+        write_archive(out,
+                      &self.header,
+                      &sources,
+                      options,
+                      |table_index, entry_index| &self.tables[table_index].contents[entry_index][..])
+    }
+}
+
+/// The file IDs and sizes of a table that's about to be written, in its current order.
+pub(crate) struct TableSource {
+    pub file_type: DrsFileType,
+    pub files: Vec<(u32, u64)>,
+}
+
+/// Creates (or truncates) a file and hands it to `write`. When `atomic` is set the data goes
+/// to a temporary sibling of the file first, which is removed if anything fails and renamed
+/// over the destination otherwise.
+pub(crate) fn write_file<F>(file_name: &Path, atomic: bool, write: F) -> Result<()>
+    where F: FnOnce(&mut BufWriter<File>) -> Result<()>
+{
+    if !atomic {
+        let mut out = BufWriter::new(File::create(file_name)?);
+        write(&mut out)?;
+        out.flush()?;
+        return Ok(());
+    }
+
+    let name = match file_name.file_name() {
+        Some(name) => name.to_string_lossy(),
+        None => return Err(ErrorKind::InvalidFileName(file_name.display().to_string()).into()),
+    };
+    let temp_name = file_name.with_file_name(format!(".{}.{}.tmp", name, process::id()));
+    let result = File::create(&temp_name).map_err(Error::from).and_then(|file| {
+        let mut out = BufWriter::new(file);
+        write(&mut out)?;
+        let file = out.into_inner().map_err(|e| e.into_error())?;
+        file.sync_all()?;
+        Ok(())
+    });
+    match result.and_then(|()| fs::rename(&temp_name, file_name).map_err(Error::from)) {
+        Ok(()) => Ok(()),
+        Err(e) => {
+            let _ = fs::remove_file(&temp_name);
+            Err(e)
+        }
+    }
+}
+
+/// Lays out and writes an archive. `contents` is called with the index of a table in
+/// `tables` and the index of a file within it, once per file, in the order they're written.
+pub(crate) fn write_archive<'a, W, F>(out: &mut W,
+                                      header: &DrsHeader,
+                                      tables: &[TableSource],
+                                      options: &DrsWriteOptions,
+                                      mut contents: F)
+                                      -> Result<()>
+    where W: Write,
+          F: FnMut(usize, usize) -> &'a [u8]
+{
+    let layout = compute_layout(header, tables, options)?;
+
+    layout.header.write_to(out)?;
+    for table in &layout.tables {
+        table.header.write_to(out)?;
+    }
+    for table in &layout.tables {
+        for entry in &table.entries {
+            entry.write_to(out)?;
+        }
+    }
+
+    let mut position = first_data_offset(&layout);
+    for table in &layout.tables {
+        for (entry, &index) in table.entries.iter().zip(&table.order) {
+            write_padding(out, entry.file_offset - position)?;
+            out.write_all(contents(table.source, index))?;
+            position = entry.file_offset + entry.file_size;
+        }
+    }
+
+    if let Some(ref tag) = options.build_tag {
+        out.write_all(tag.as_bytes())?;
+        out.write_u32(to_u32(tag.len() as u64)?)?;
+        out.write_all(BUILD_TAG_MAGIC)?;
+    }
+    Ok(())
+}
+
+pub(crate) fn compute_layout(header: &DrsHeader, tables: &[TableSource], options: &DrsWriteOptions) -> Result<DrsLayout> {
+    let align = match options.align {
+        Some(align) if align > 1 => align as u64,
+        _ => 1,
+    };
+
+    let mut selected: Vec<_> = tables.iter()
+        .enumerate()
+        .filter(|&(_, table)| match options.include_types {
+            Some(ref types) => types.contains(&table.file_type),
+            None => true,
+        })
+        .collect();
+    if options.entry_order == EntryOrder::ById {
+        selected.sort_by_key(|&(_, table)| u32::from(table.file_type));
+    }
+
+    let mut table_offset = header.serialized_len() + TABLE_HEADER_LEN * selected.len() as u64;
+    let mut position = metadata_end(header.serialized_len(),
+                                    selected.iter().map(|&(_, t)| t.files.len() as u64),
+                                    TABLE_ENTRY_LEN);
+    let mut file_offset = None;
+
+    let mut table_layouts = Vec::with_capacity(selected.len());
+    for (table_index, table) in selected {
+        let mut order: Vec<usize> = (0..table.files.len()).collect();
+        if options.entry_order == EntryOrder::ById {
+            order.sort_by_key(|&index| table.files[index].0);
+        }
+
+        let mut entries = Vec::with_capacity(table.files.len());
+        for &index in &order {
+            let (file_id, file_size) = table.files[index];
+            position = position.div_ceil(align) * align;
+            file_offset = file_offset.or(Some(position));
+            to_u32(position)?;
+            to_u32(file_size)?;
+            entries.push(DrsTableEntry {
+                file_id,
+                file_offset: position,
+                file_size,
+            });
+            position += file_size;
+        }
+        to_u32(position)?;
+
+        let table_header = DrsTableHeader {
+            file_type: table.file_type,
+            table_offset: to_u32(table_offset)?,
+            file_count: to_u32(entries.len() as u64)?,
+        };
+        table_offset += TABLE_ENTRY_LEN * entries.len() as u64;
+        table_layouts.push(DrsTableLayout {
+            header: table_header,
+            entries,
+            source: table_index,
+            order,
+        });
+    }
+
+    let header = DrsHeader {
+        copyright_info: header.copyright_info,
+        file_version: header.file_version,
+        file_type: header.file_type,
+        table_count: to_u32(table_layouts.len() as u64)?,
+        file_offset: to_u32(file_offset.unwrap_or(table_offset))?,
+    };
+
+    Ok(DrsLayout {
+        header,
+        tables: table_layouts,
+    })
+}
+
+/// The offset just past the entry headers, which is where file data begins (before padding).
+fn first_data_offset(layout: &DrsLayout) -> u64 {
+    metadata_end(layout.header.serialized_len(),
+                 layout.tables.iter().map(|t| t.entries.len() as u64),
+                 TABLE_ENTRY_LEN)
+}
+
+fn write_padding<W: Write>(out: &mut W, len: u64) -> Result<()> {
+    const ZEROS: [u8; 16] = [0u8; 16];
+    let mut remaining = len;
+    while remaining > 0 {
+        let chunk = remaining.min(ZEROS.len() as u64);
+        out.write_all(&ZEROS[..chunk as usize])?;
+        remaining -= chunk;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::DrsBuilder;
+    use crate::drs::DrsGameType;
+    use crate::fixtures::write_temp_archive;
+
+    use std::io;
+
+    fn build_and_write(files: &[(DrsFileType, u32, &[u8])]) -> Vec<u8> {
+        let mut builder = DrsBuilder::new(DrsGameType::AOE);
+        for &(file_type, file_id, contents) in files {
+            builder.add_file(file_type, file_id, contents.to_vec()).unwrap();
+        }
+        let mut bytes = Vec::new();
+        builder.build().unwrap().write_to_with_options(&mut bytes, &DrsWriteOptions::default()).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn output_does_not_depend_on_insertion_order() {
+        let files: [(DrsFileType, u32, &[u8]); 4] = [(DrsFileType::Wav, 7, b"RIFF"),
+                                                     (DrsFileType::Slp, 2, b"two"),
+                                                     (DrsFileType::Binary, 50500, b"palette"),
+                                                     (DrsFileType::Slp, 1, b"one")];
+        let mut reversed = files;
+        reversed.reverse();
+        assert_eq!(build_and_write(&files), build_and_write(&reversed));
+    }
+
+    #[test]
+    fn as_inserted_keeps_entries_in_insertion_order() {
+        let mut builder = DrsBuilder::new(DrsGameType::AOE);
+        for &file_id in &[5, 1, 3] {
+            builder.add_file(DrsFileType::Slp, file_id, vec![file_id as u8]).unwrap();
+        }
+        let archive = builder.build().unwrap();
+
+        for &(entry_order, expected) in &[(EntryOrder::AsInserted, [5, 1, 3]), (EntryOrder::ById, [1, 3, 5])] {
+            let options = DrsWriteOptions { entry_order, ..DrsWriteOptions::default() };
+            let mut bytes = Vec::new();
+            archive.write_to_with_options(&mut bytes, &options).unwrap();
+            let read_back = DrsFile::from_bytes(&bytes).unwrap();
+            let table = read_back.find_table(DrsFileType::Slp).unwrap();
+            assert_eq!(table.file_ids(), expected);
+            // Data follows the entries in the same order.
+            assert!(table.entries.windows(2).all(|pair| pair[0].file_offset < pair[1].file_offset));
+            assert_eq!(read_back.find_file(5).unwrap(), &[5]);
+        }
+    }
+
+    #[test]
+    fn write_to_file_reads_back() {
+        let mut builder = DrsBuilder::new(DrsGameType::SWBG);
+        builder.add_file(DrsFileType::Slp, 1, b"one".to_vec()).unwrap();
+        builder.add_file(DrsFileType::Wav, 7, b"RIFF".to_vec()).unwrap();
+        let archive = builder.build().unwrap();
+
+        for &atomic in &[false, true] {
+            // Starts out as garbage, so that the write has to replace it.
+            let path = write_temp_archive(&format!("write_to_file_{}.drs", atomic), b"garbage");
+            let options = DrsWriteOptions { atomic, ..DrsWriteOptions::default() };
+            if atomic {
+                archive.write_to_file_with_options(&path, &options).unwrap();
+            } else {
+                archive.write_to_file(&path).unwrap();
+            }
+            assert_eq!(DrsFile::read_from_file(&path).unwrap(), archive);
+
+            let name = path.file_name().unwrap().to_string_lossy();
+            assert!(!path.with_file_name(format!(".{}.{}.tmp", name, process::id())).exists());
+        }
+    }
+
+    fn write_with_align(drs_file: &DrsFile, align: Option<u32>) -> Vec<u8> {
+        let options = DrsWriteOptions { align, ..DrsWriteOptions::default() };
+        let mut bytes = Vec::new();
+        drs_file.write_to_with_options(&mut bytes, &options).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn alignment_pads_between_odd_sized_files() {
+        let mut builder = DrsBuilder::new(DrsGameType::AOE);
+        builder.add_file(DrsFileType::Slp, 1, b"abc".to_vec()).unwrap();
+        builder.add_file(DrsFileType::Slp, 2, b"defgh".to_vec()).unwrap();
+        builder.add_file(DrsFileType::Slp, 3, b"i".to_vec()).unwrap();
+        let archive = builder.build().unwrap();
+
+        // The data starts at 112, so aligned files go at 112, 116, and 124.
+        for &(align, step, offsets) in &[(Some(4), 4, [112, 116, 124]), (None, 1, [112, 115, 120])] {
+            let bytes = write_with_align(&archive, align);
+            let read_back = DrsFile::from_bytes(&bytes).unwrap();
+            assert_eq!(read_back, archive);
+
+            let entries = &read_back.find_table(DrsFileType::Slp).unwrap().entries;
+            assert_eq!(entries.iter().map(|e| e.file_offset).collect::<Vec<_>>(), offsets);
+            let mut position = read_back.header.file_offset as u64;
+            for entry in entries {
+                assert_eq!(entry.file_offset % step, 0);
+                let padding = entry.file_offset - position;
+                assert_eq!(padding, (step - position % step) % step);
+                assert!(bytes[position as usize..entry.file_offset as usize].iter().all(|&b| b == 0));
+                position = entry.file_offset + entry.file_size;
+            }
+            assert_eq!(position, bytes.len() as u64);
+        }
+    }
+
+    /// A sink that keeps nothing, only how much it was handed and the largest single write.
+    #[derive(Default)]
+    struct CountingSink {
+        total: u64,
+        largest_write: usize,
+    }
+
+    impl Write for CountingSink {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.total += buf.len() as u64;
+            self.largest_write = self.largest_write.max(buf.len());
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn large_entry_is_streamed_without_a_serialized_copy() {
+        const LARGE: usize = 8 * 1024 * 1024;
+        let mut builder = DrsBuilder::new(DrsGameType::AOE);
+        builder.add_file(DrsFileType::Slp, 1, vec![0xAB; LARGE]).unwrap();
+        builder.add_file(DrsFileType::Wav, 2, b"RIFF".to_vec()).unwrap();
+        let archive = builder.build().unwrap();
+
+        let mut sink = CountingSink::default();
+        archive.write_to_with_options(&mut sink, &DrsWriteOptions::default()).unwrap();
+        assert_eq!(sink.total, archive.min_encoded_len());
+        // The large entry goes out as the slice the archive already holds; nothing bigger
+        // (such as the whole archive serialized up front) is ever handed to the sink.
+        assert_eq!(sink.largest_write, LARGE);
+    }
+}